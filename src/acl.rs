@@ -0,0 +1,172 @@
+use eva_common::{EResult, Error};
+use log::warn;
+use serde::Deserialize;
+use std::collections::HashSet;
+use std::sync::RwLock;
+
+static ACL: RwLock<Option<Acl>> = RwLock::new(None);
+
+#[derive(bmart::tools::EnumStr, Copy, Clone)]
+pub enum MethodGroup {
+    View,
+    Control,
+    System,
+    Dangerous,
+}
+
+/// Methods, which are not listed, are considered as dangerous. URL list changes can unlock the
+/// kiosk navigation, so they are dangerous as well
+pub fn method_group(method: &str) -> MethodGroup {
+    match method {
+        "test" | "info" | "screenshot" | "urls.list" | "monitors" => MethodGroup::View,
+        "login" | "logout" | "alert" | "navigate" | "zoom" | "display" | "display.rotate"
+        | "volume" => MethodGroup::Control,
+        "reload" | "stop" | "reboot" | "reboot.cancel" | "poweroff" | "session.restart"
+        | "config.reload" => MethodGroup::System,
+        _ => MethodGroup::Dangerous,
+    }
+}
+
+/// Allowed caller names per method group, a group which is not set is allowed for any caller
+#[derive(Deserialize, Clone, Default, Eq, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct Acl {
+    #[serde(default)]
+    view: Option<Vec<String>>,
+    #[serde(default)]
+    control: Option<Vec<String>>,
+    #[serde(default)]
+    system: Option<Vec<String>>,
+    #[serde(default)]
+    dangerous: Option<Vec<String>>,
+    /// methods, disabled for all callers
+    #[serde(default)]
+    disabled: HashSet<String>,
+}
+
+impl Acl {
+    fn group(&self, group: MethodGroup) -> Option<&[String]> {
+        match group {
+            MethodGroup::View => self.view.as_deref(),
+            MethodGroup::Control => self.control.as_deref(),
+            MethodGroup::System => self.system.as_deref(),
+            MethodGroup::Dangerous => self.dangerous.as_deref(),
+        }
+    }
+    fn check(&self, method: &str, sender: &str) -> EResult<()> {
+        if self.disabled.contains(method) {
+            warn!("{} called disabled method {}", sender, method);
+            return Err(Error::access(format!("method {} is disabled", method)));
+        }
+        let group = method_group(method);
+        if let Some(names) = self.group(group) {
+            if !names.iter().any(|mask| name_matches(mask, sender)) {
+                warn!("{} is not allowed to call {} ({})", sender, method, group);
+                return Err(Error::access(format!(
+                    "{} is not allowed to call {} methods",
+                    sender, group
+                )));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// "*" matches any caller, "name*" matches callers with the name prefix
+fn name_matches(mask: &str, name: &str) -> bool {
+    if let Some(prefix) = mask.strip_suffix('*') {
+        name.starts_with(prefix)
+    } else {
+        mask == name
+    }
+}
+
+pub fn set(acl: Option<Acl>) {
+    *ACL.write().unwrap() = acl;
+}
+
+pub fn check(method: &str, sender: &str) -> EResult<()> {
+    ACL.read()
+        .unwrap()
+        .as_ref()
+        .map_or(Ok(()), |acl| acl.check(method, sender))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn acl(source: &str) -> Acl {
+        serde_yaml::from_str(source).unwrap()
+    }
+
+    #[test]
+    fn test_method_group() {
+        assert!(matches!(method_group("info"), MethodGroup::View));
+        assert!(matches!(method_group("screenshot"), MethodGroup::View));
+        assert!(matches!(method_group("navigate"), MethodGroup::Control));
+        assert!(matches!(method_group("display"), MethodGroup::Control));
+        assert!(matches!(method_group("reboot"), MethodGroup::System));
+        assert!(matches!(method_group("config.reload"), MethodGroup::System));
+        for method in [
+            "eval",
+            "eval.result",
+            "dev.open",
+            "urls.add",
+            "urls.remove",
+            "urls.allow_any",
+            "unknown",
+            "",
+        ] {
+            assert!(
+                matches!(method_group(method), MethodGroup::Dangerous),
+                "{}",
+                method
+            );
+        }
+    }
+
+    #[test]
+    fn test_unset_group() {
+        let acl = acl("system: []");
+        assert!(acl.check("info", "any").is_ok());
+        assert!(acl.check("eval", "").is_ok());
+        assert!(acl.check("reboot", "eva.kioskman.default").is_err());
+        assert!(Acl::default().check("eval", "any").is_ok());
+    }
+
+    #[test]
+    fn test_disabled() {
+        let acl = acl("dangerous: ['*']\ndisabled: [eval]");
+        assert!(acl.check("eval", "eva.kioskman.default").is_err());
+        assert!(acl.check("eval.result", "eva.kioskman.default").is_ok());
+        // disabled methods are checked before groups
+        let acl = Acl {
+            disabled: ["info".to_owned()].into_iter().collect(),
+            ..Acl::default()
+        };
+        assert!(acl.check("info", "any").is_err());
+    }
+
+    #[test]
+    fn test_masks() {
+        let acl = acl("view: ['*']\ncontrol: ['eva.kioskman.*', 'admin']\n\
+            system: ['eva.kioskman.default']\ndangerous: []");
+        assert!(acl.check("info", "").is_ok());
+        assert!(acl.check("info", "any").is_ok());
+        assert!(acl.check("navigate", "eva.kioskman.default").is_ok());
+        assert!(acl.check("navigate", "eva.kioskman.").is_ok());
+        assert!(acl.check("navigate", "eva.kioskman").is_err());
+        assert!(acl.check("navigate", "admin").is_ok());
+        assert!(acl.check("navigate", "admin2").is_err());
+        assert!(acl.check("navigate", "").is_err());
+        assert!(acl.check("reboot", "eva.kioskman.default").is_ok());
+        assert!(acl.check("reboot", "eva.kioskman.default2").is_err());
+        assert!(acl.check("urls.add", "eva.kioskman.default").is_err());
+        assert!(acl.check("eval", "").is_err());
+        assert!(name_matches("*", ""));
+        assert!(name_matches("", ""));
+        assert!(!name_matches("", "a"));
+        assert!(!name_matches("a*", ""));
+    }
+}
@@ -0,0 +1,276 @@
+use crate::common::{PanelEvent, UEvent};
+use crate::connectivity::ConnectivityInfo;
+use crate::display::DisplayState;
+use crate::idle::IdleInfo;
+use crate::policy::UrlList;
+use eva_common::{EResult, Error};
+use log::{debug, info, warn};
+use once_cell::sync::OnceCell;
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::sync::{Arc, RwLock};
+use tao::event_loop::EventLoopProxy;
+
+/// The bridge API version, incremented on incompatible changes
+pub const API_VERSION: u32 = 1;
+
+/// Injects window.evapanel object, each method returns a promise
+pub const BRIDGE_JS: &str = r"(() => {
+    let next_id = 1;
+    const pending = new Map();
+    const call = (method, params) => new Promise((resolve, reject) => {
+      const id = next_id++;
+      pending.set(id, { resolve, reject });
+      window.ipc.postMessage(JSON.stringify({ v: 1, id, method, params: params || null }));
+    });
+    window.__evapanel_reply = (id, error, result) => {
+      const p = pending.get(id);
+      if (!p) return;
+      pending.delete(id);
+      if (error === null) {
+        p.resolve(result);
+      } else {
+        p.reject(new Error(error));
+      }
+    };
+    window.evapanel = Object.freeze({
+      version: 1,
+      info: () => call('info'),
+      display: (params) => call('display', params),
+      zoom: (level) => call('zoom', { level }),
+      user: (user) => call('user', { user: user === undefined ? null : user }),
+      reload: () => call('reload'),
+    });
+  })();";
+
+static CONFIG: RwLock<Option<Arc<Bridge>>> = RwLock::new(None);
+static PROXY: OnceCell<EventLoopProxy<UEvent>> = OnceCell::new();
+/// the user, reported by the HMI app
+static USER: RwLock<Option<String>> = RwLock::new(None);
+
+#[derive(Deserialize, bmart::tools::EnumStr, Copy, Clone, Eq, PartialEq, Hash)]
+#[serde(rename_all = "snake_case")]
+pub enum Method {
+    Info,
+    Display,
+    Zoom,
+    User,
+    Reload,
+}
+
+/// Calls are rejected if the bridge is not configured
+#[derive(Deserialize, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct BridgeConfig {
+    /// allowed methods
+    #[serde(default)]
+    methods: HashSet<Method>,
+    /// URL patterns (the same format as allowed_urls) of pages, allowed to call the bridge, any
+    /// page if empty
+    #[serde(default)]
+    urls: HashSet<String>,
+}
+
+impl BridgeConfig {
+    /// Returns a list of (key, problem)
+    pub fn validate(&self) -> Vec<(&'static str, String)> {
+        let mut problems = Vec::new();
+        if let Err(e) = crate::policy::check_patterns(&self.urls) {
+            problems.push(("bridge.urls", e.to_string()));
+        }
+        problems
+    }
+}
+
+pub struct Bridge {
+    methods: HashSet<Method>,
+    urls: Option<UrlList>,
+}
+
+impl Bridge {
+    pub fn new(config: &BridgeConfig) -> EResult<Self> {
+        Ok(Self {
+            methods: config.methods.clone(),
+            urls: if config.urls.is_empty() {
+                None
+            } else {
+                Some(UrlList::new(&config.urls)?)
+            },
+        })
+    }
+}
+
+pub fn set(bridge: Option<Bridge>) {
+    *CONFIG.write().unwrap() = bridge.map(Arc::new);
+}
+
+/// Sets the event loop proxy, must be called before the Web view is created
+pub fn init(proxy: EventLoopProxy<UEvent>) {
+    let _ = PROXY.set(proxy);
+}
+
+pub fn user() -> Option<String> {
+    USER.read().unwrap().clone()
+}
+
+#[derive(Deserialize)]
+struct Call {
+    v: u32,
+    id: u64,
+    method: String,
+    #[serde(default)]
+    params: serde_json::Value,
+}
+
+#[derive(Serialize)]
+struct BridgeInfo {
+    api_version: u32,
+    agent: &'static str,
+    version: &'static str,
+    arch: &'static str,
+    home_url: String,
+    user: Option<String>,
+    display: Option<DisplayState>,
+    idle: IdleInfo,
+    connectivity: ConnectivityInfo,
+}
+
+fn params<T: serde::de::DeserializeOwned>(params: serde_json::Value) -> EResult<T> {
+    serde_json::from_value(params).map_err(Error::invalid_params)
+}
+
+fn send_event(event: UEvent) -> EResult<()> {
+    PROXY
+        .get()
+        .ok_or_else(|| Error::failed("the bridge is not initialized"))?
+        .send_event(event)
+        .map_err(Error::failed)
+}
+
+async fn execute(method: Method, p: serde_json::Value) -> EResult<serde_json::Value> {
+    match method {
+        Method::Info => {
+            let info = BridgeInfo {
+                api_version: API_VERSION,
+                agent: crate::AGENT_NAME,
+                version: crate::VERSION,
+                arch: crate::ARCH,
+                home_url: crate::home_url(),
+                user: user(),
+                display: crate::display::state().await.ok(),
+                idle: crate::idle::info(),
+                connectivity: crate::connectivity::info(),
+            };
+            serde_json::to_value(info).map_err(Error::failed)
+        }
+        Method::Display => {
+            #[derive(Deserialize)]
+            #[serde(deny_unknown_fields)]
+            struct ParamsDisplay {
+                on: Option<bool>,
+                brightness: Option<f32>,
+            }
+            if p.is_null() {
+                return serde_json::to_value(crate::display::state().await?).map_err(Error::failed);
+            }
+            let p: ParamsDisplay = params(p)?;
+            crate::display::apply(p.on, p.brightness).await?;
+            Ok(serde_json::Value::Null)
+        }
+        Method::Zoom => {
+            #[derive(Deserialize)]
+            #[serde(deny_unknown_fields)]
+            struct ParamsZoom {
+                level: f64,
+            }
+            let p: ParamsZoom = params(p)?;
+            crate::config::check_zoom(p.level)?;
+            send_event(UEvent::Zoom(p.level))?;
+            Ok(serde_json::Value::Null)
+        }
+        Method::User => {
+            #[derive(Deserialize)]
+            #[serde(deny_unknown_fields)]
+            struct ParamsUser {
+                user: Option<String>,
+            }
+            let p: ParamsUser = params(p)?;
+            let mut current = USER.write().unwrap();
+            if *current != p.user {
+                info!("user: {}", p.user.as_deref().unwrap_or("-"));
+                current.clone_from(&p.user);
+                crate::eapi::notify(PanelEvent::User { user: p.user });
+            }
+            Ok(serde_json::Value::Null)
+        }
+        Method::Reload => {
+            send_event(UEvent::ReloadPage)?;
+            Ok(serde_json::Value::Null)
+        }
+    }
+}
+
+/// Checks the call against the allowlist
+fn check(call: &Call, page_url: &str) -> EResult<Method> {
+    if call.v != API_VERSION {
+        return Err(Error::unsupported(format!(
+            "bridge API version {} is not supported",
+            call.v
+        )));
+    }
+    let bridge = CONFIG
+        .read()
+        .unwrap()
+        .clone()
+        .ok_or_else(|| Error::access("the bridge is disabled"))?;
+    let method: Method = serde_json::from_value(serde_json::Value::String(call.method.clone()))
+        .map_err(|_| Error::not_found(format!("method {} not found", call.method)))?;
+    if !bridge.methods.contains(&method) {
+        warn!("bridge method {} is not allowed ({})", method, page_url);
+        return Err(Error::access(format!("method {} is not allowed", method)));
+    }
+    if let Some(ref urls) = bridge.urls {
+        if !urls.matches(page_url) {
+            warn!("bridge calls from {} are not allowed", page_url);
+            return Err(Error::access("the page is not allowed to call the bridge"));
+        }
+    }
+    Ok(method)
+}
+
+/// Handles an IPC message from the page
+pub fn handle(page_url: &str, body: &str) {
+    let call: Call = match serde_json::from_str(body) {
+        Ok(v) => v,
+        Err(e) => {
+            debug!("invalid IPC message from {}: {}", page_url, e);
+            return;
+        }
+    };
+    debug!("bridge call {} from {}", call.method, page_url);
+    let id = call.id;
+    match check(&call, page_url) {
+        Ok(method) => {
+            crate::spawn(async move {
+                let result = execute(method, call.params)
+                    .await
+                    .map_err(|e| e.to_string());
+                let _ = send_event(UEvent::BridgeReply(id, result));
+            });
+        }
+        Err(e) => {
+            let _ = send_event(UEvent::BridgeReply(id, Err(e.to_string())));
+        }
+    }
+}
+
+pub fn reply_js(id: u64, result: Result<serde_json::Value, String>) -> String {
+    let (error, value) = match result {
+        Ok(v) => (serde_json::Value::Null, v),
+        Err(e) => (serde_json::Value::String(e), serde_json::Value::Null),
+    };
+    format!(
+        "window.__evapanel_reply && window.__evapanel_reply({}, {}, {})",
+        id, error, value
+    )
+}
@@ -0,0 +1,364 @@
+use crate::common::{system_cmd_t, AlertLevel, UEvent};
+use eva_common::{err_logger, EResult, Error};
+use log::{debug, info, warn};
+use serde::{Deserialize, Serialize};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, Instant};
+use tao::event_loop::EventLoopProxy;
+
+err_logger!();
+
+/// the minimal delay for system actions, lets the caller receive the reply
+const MIN_DELAY: Duration = Duration::from_secs(2);
+/// countdown alert interval
+const ALERT_INTERVAL: Duration = Duration::from_secs(10);
+const CANCEL_ALERT_TIMEOUT: u16 = 5;
+
+static COMMANDS: RwLock<Option<Arc<Commands>>> = RwLock::new(None);
+static PENDING: Mutex<Option<Pending>> = Mutex::new(None);
+
+#[derive(bmart::tools::EnumStr, Copy, Clone)]
+pub enum Action {
+    Reboot,
+    Poweroff,
+    SessionRestart,
+    DisplayOn,
+    DisplayOff,
+    Brightness,
+    Volume,
+}
+
+const ACTIONS: &[Action] = &[
+    Action::Reboot,
+    Action::Poweroff,
+    Action::SessionRestart,
+    Action::DisplayOn,
+    Action::DisplayOff,
+    Action::Brightness,
+    Action::Volume,
+];
+
+impl Action {
+    /// Placeholders, allowed in the command template
+    fn vars(self) -> &'static [&'static str] {
+        match self {
+            Action::Reboot | Action::Poweroff | Action::SessionRestart => &[],
+            Action::DisplayOn | Action::DisplayOff => &["monitor"],
+            Action::Brightness => &["monitor", "brightness"],
+            Action::Volume => &["volume"],
+        }
+    }
+    /// Used in HMI alerts
+    fn title(self) -> &'static str {
+        match self {
+            Action::Reboot => "reboot",
+            Action::Poweroff => "power off",
+            Action::SessionRestart => "session restart",
+            Action::DisplayOn => "display on",
+            Action::DisplayOff => "display off",
+            Action::Brightness => "brightness",
+            Action::Volume => "volume",
+        }
+    }
+    fn config_key(self) -> &'static str {
+        match self {
+            Action::Reboot => "commands.reboot",
+            Action::Poweroff => "commands.poweroff",
+            Action::SessionRestart => "commands.session_restart",
+            Action::DisplayOn => "commands.display_on",
+            Action::DisplayOff => "commands.display_off",
+            Action::Brightness => "commands.brightness",
+            Action::Volume => "commands.volume",
+        }
+    }
+}
+
+#[inline]
+fn default_timeout() -> f64 {
+    5.0
+}
+
+/// A shell command, either a string or a map with "cmd", "timeout" (seconds) and "exit_ok"
+/// (additionally accepted exit codes) fields. "{name}" placeholders are replaced with
+/// shell-quoted values
+#[derive(Deserialize, Clone, PartialEq)]
+#[serde(from = "TemplateConfig")]
+pub struct CommandTemplate {
+    cmd: String,
+    timeout: f64,
+    exit_ok: Vec<i32>,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum TemplateConfig {
+    Cmd(String),
+    Full(FullTemplateConfig),
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct FullTemplateConfig {
+    cmd: String,
+    #[serde(default = "default_timeout")]
+    timeout: f64,
+    #[serde(default)]
+    exit_ok: Vec<i32>,
+}
+
+impl From<TemplateConfig> for CommandTemplate {
+    fn from(config: TemplateConfig) -> Self {
+        match config {
+            TemplateConfig::Cmd(cmd) => Self::new(&cmd),
+            TemplateConfig::Full(c) => Self {
+                cmd: c.cmd,
+                timeout: c.timeout,
+                exit_ok: c.exit_ok,
+            },
+        }
+    }
+}
+
+fn shell_quote(value: &str) -> String {
+    format!("'{}'", value.replace('\'', r"'\''"))
+}
+
+/// Returns "{name}" placeholders, shell "${VAR}" variables are skipped
+fn placeholders(cmd: &str) -> Vec<&str> {
+    let mut result = Vec::new();
+    let mut rest = cmd;
+    while let Some(start) = rest.find('{') {
+        let after = &rest[start + 1..];
+        let Some(end) = after.find('}') else {
+            break;
+        };
+        let name = &after[..end];
+        if !rest[..start].ends_with('$')
+            && !name.is_empty()
+            && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+        {
+            result.push(name);
+        }
+        rest = &after[end + 1..];
+    }
+    result
+}
+
+impl CommandTemplate {
+    fn new(cmd: &str) -> Self {
+        Self {
+            cmd: cmd.to_owned(),
+            timeout: default_timeout(),
+            exit_ok: Vec::new(),
+        }
+    }
+    fn validate(&self, action: Action) -> Vec<(&'static str, String)> {
+        let mut problems = Vec::new();
+        let key = action.config_key();
+        if self.cmd.trim().is_empty() {
+            problems.push((key, "command is empty".to_owned()));
+        }
+        if self.timeout <= 0.0 || !self.timeout.is_finite() {
+            problems.push((key, format!("invalid timeout: {}", self.timeout)));
+        }
+        for name in placeholders(&self.cmd) {
+            if !action.vars().contains(&name) {
+                problems.push((key, format!("unknown placeholder {{{}}}", name)));
+            }
+        }
+        problems
+    }
+    async fn run(&self, vars: &[(&str, String)]) -> EResult<()> {
+        let mut cmd = self.cmd.clone();
+        for (name, value) in vars {
+            cmd = cmd.replace(&format!("{{{}}}", name), &shell_quote(value));
+        }
+        debug!("executing: {}", cmd);
+        let timeout = Duration::try_from_secs_f64(self.timeout).map_err(Error::invalid_params)?;
+        system_cmd_t("sh", vec!["-c", cmd.as_str()], timeout, &self.exit_ok).await
+    }
+}
+
+#[inline]
+fn default_reboot() -> CommandTemplate {
+    CommandTemplate::new("reboot")
+}
+
+#[inline]
+fn default_poweroff() -> CommandTemplate {
+    CommandTemplate::new("poweroff")
+}
+
+/// System command templates, display commands override the display backend if set
+#[derive(Deserialize, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct Commands {
+    #[serde(default = "default_reboot")]
+    reboot: CommandTemplate,
+    #[serde(default = "default_poweroff")]
+    poweroff: CommandTemplate,
+    #[serde(default)]
+    session_restart: Option<CommandTemplate>,
+    #[serde(default)]
+    display_on: Option<CommandTemplate>,
+    #[serde(default)]
+    display_off: Option<CommandTemplate>,
+    #[serde(default)]
+    brightness: Option<CommandTemplate>,
+    #[serde(default)]
+    volume: Option<CommandTemplate>,
+}
+
+impl Default for Commands {
+    fn default() -> Self {
+        Self {
+            reboot: default_reboot(),
+            poweroff: default_poweroff(),
+            session_restart: None,
+            display_on: None,
+            display_off: None,
+            brightness: None,
+            volume: None,
+        }
+    }
+}
+
+impl Commands {
+    fn template(&self, action: Action) -> Option<&CommandTemplate> {
+        match action {
+            Action::Reboot => Some(&self.reboot),
+            Action::Poweroff => Some(&self.poweroff),
+            Action::SessionRestart => self.session_restart.as_ref(),
+            Action::DisplayOn => self.display_on.as_ref(),
+            Action::DisplayOff => self.display_off.as_ref(),
+            Action::Brightness => self.brightness.as_ref(),
+            Action::Volume => self.volume.as_ref(),
+        }
+    }
+    /// Returns a list of (key, problem)
+    pub fn validate(&self) -> Vec<(&'static str, String)> {
+        ACTIONS
+            .iter()
+            .filter_map(|action| self.template(*action).map(|t| t.validate(*action)))
+            .flatten()
+            .collect()
+    }
+}
+
+pub fn set(commands: Commands) {
+    *COMMANDS.write().unwrap() = Some(Arc::new(commands));
+}
+
+fn commands() -> Arc<Commands> {
+    COMMANDS.read().unwrap().clone().unwrap_or_default()
+}
+
+pub fn is_set(action: Action) -> bool {
+    commands().template(action).is_some()
+}
+
+struct Pending {
+    action: Action,
+    deadline: Instant,
+    task: tokio::task::AbortHandle,
+}
+
+#[derive(Serialize)]
+pub struct PendingInfo {
+    action: String,
+    /// seconds left
+    remaining: f64,
+}
+
+pub fn pending() -> Option<PendingInfo> {
+    PENDING.lock().unwrap().as_ref().map(|p| PendingInfo {
+        action: p.action.to_string(),
+        remaining: p
+            .deadline
+            .saturating_duration_since(Instant::now())
+            .as_secs_f64(),
+    })
+}
+
+fn countdown_alert(proxy: &EventLoopProxy<UEvent>, action: Action, remaining: Duration) {
+    let secs = remaining.as_secs().max(1);
+    let _ = proxy.send_event(UEvent::Alert(
+        format!("System {} in {} sec", action.title(), secs),
+        AlertLevel::Warning,
+        u16::try_from(secs.min(ALERT_INTERVAL.as_secs())).unwrap_or(u16::MAX),
+    ));
+}
+
+/// Runs a system action after the delay, the HMI displays a countdown alert if the delay is
+/// set. Only one action can be pending
+pub fn schedule(
+    action: Action,
+    delay: Option<Duration>,
+    proxy: EventLoopProxy<UEvent>,
+) -> EResult<()> {
+    let mut pending = PENDING.lock().unwrap();
+    if let Some(ref p) = *pending {
+        return Err(Error::busy(format!("{} is already pending", p.action)));
+    }
+    // verify the command is configured before scheduling
+    commands()
+        .template(action)
+        .ok_or_else(|| Error::unsupported(format!("{} command is not configured", action)))?;
+    let deadline = Instant::now() + delay.unwrap_or_default().max(MIN_DELAY);
+    warn!(
+        "{} scheduled in {:.0} sec",
+        action,
+        deadline
+            .saturating_duration_since(Instant::now())
+            .as_secs_f64()
+    );
+    let task = tokio::spawn(async move {
+        if delay.is_some() {
+            loop {
+                let remaining = deadline.saturating_duration_since(Instant::now());
+                if remaining.is_zero() {
+                    break;
+                }
+                countdown_alert(&proxy, action, remaining);
+                tokio::time::sleep(remaining.min(ALERT_INTERVAL)).await;
+            }
+        } else {
+            tokio::time::sleep_until(deadline.into()).await;
+        }
+        PENDING.lock().unwrap().take();
+        run(action, &[]).await.log_ef();
+    });
+    pending.replace(Pending {
+        action,
+        deadline,
+        task: task.abort_handle(),
+    });
+    Ok(())
+}
+
+/// Cancels the pending action, returns the action cancelled
+pub fn cancel(proxy: &EventLoopProxy<UEvent>) -> EResult<Action> {
+    let p = PENDING
+        .lock()
+        .unwrap()
+        .take()
+        .ok_or_else(|| Error::not_found("no pending actions"))?;
+    p.task.abort();
+    warn!("{} cancelled", p.action);
+    let _ = proxy.send_event(UEvent::Alert(
+        format!("System {} cancelled", p.action.title()),
+        AlertLevel::Info,
+        CANCEL_ALERT_TIMEOUT,
+    ));
+    Ok(p.action)
+}
+
+/// Runs the command, returns an error if the command is not configured
+pub async fn run(action: Action, vars: &[(&str, String)]) -> EResult<()> {
+    let commands = commands();
+    let template = commands
+        .template(action)
+        .ok_or_else(|| Error::unsupported(format!("{} command is not configured", action)))?;
+    info!("executing {} command", action);
+    template.run(vars).await
+}
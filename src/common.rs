@@ -1,14 +1,22 @@
+use crate::commands::PendingInfo;
+use crate::config::ReloadReport;
+use crate::connectivity::ConnectivityInfo;
+use crate::display::DisplayState;
+use crate::hang::Step;
+use crate::idle::IdleInfo;
 use eva_common::{EResult, Error};
-use serde::{Deserialize, Serialize};
+use serde::{Deserialize, Serialize, Serializer};
 use std::ffi::OsStr;
+use std::fmt;
 use std::future::Future;
+use std::sync::atomic;
 use std::time::Duration;
 
 const CMD_TIMEOUT: Duration = Duration::from_secs(5);
 
 #[derive(Serialize, Clone)]
 pub struct PanelInfo {
-    pub(crate) home_url: String,
+    pub(crate) bus_name: String,
     pub(crate) agent: String,
     pub(crate) version: String,
     pub(crate) arch: String,
@@ -16,7 +24,7 @@ pub struct PanelInfo {
     pub(crate) debug: bool,
 }
 
-#[derive(bmart::tools::EnumStr, Serialize, Copy, Clone, Debug)]
+#[derive(bmart::tools::EnumStr, Serialize, Copy, Clone, Debug, Eq, PartialEq)]
 #[repr(u8)]
 #[serde(rename_all = "lowercase")]
 pub enum State {
@@ -37,10 +45,58 @@ impl From<u8> for State {
     }
 }
 
+#[derive(Serialize, Copy, Clone)]
+#[serde(rename_all = "lowercase")]
+pub enum PageStatus {
+    Started,
+    Finished,
+    /// the bundled error page is displayed
+    Failed,
+}
+
+impl From<wry::PageLoadEvent> for PageStatus {
+    fn from(event: wry::PageLoadEvent) -> Self {
+        match event {
+            wry::PageLoadEvent::Started => PageStatus::Started,
+            wry::PageLoadEvent::Finished => PageStatus::Finished,
+        }
+    }
+}
+
+/// Events, published to the bus
+#[derive(Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum PanelEvent {
+    State { state: State },
+    Location { url: String },
+    Page { status: PageStatus, url: String },
+    NavigationBlocked { url: String },
+    AutoLogout { url: Option<String> },
+    Connectivity { online: bool },
+    Hang { step: Step },
+    User { user: Option<String> },
+}
+
+impl PanelEvent {
+    pub fn topic(&self) -> &'static str {
+        match self {
+            PanelEvent::State { .. } => "evapanel/state",
+            PanelEvent::Location { .. } => "evapanel/location",
+            PanelEvent::Page { .. } => "evapanel/page",
+            PanelEvent::NavigationBlocked { .. } => "evapanel/navigation_blocked",
+            PanelEvent::AutoLogout { .. } => "evapanel/auto_logout",
+            PanelEvent::Connectivity { .. } => "evapanel/connectivity",
+            PanelEvent::Hang { .. } => "evapanel/hang",
+            PanelEvent::User { .. } => "evapanel/user",
+        }
+    }
+}
+
 #[derive(Serialize)]
 #[allow(clippy::struct_excessive_bools)]
 pub struct StateInfo<'a> {
-    home_url: &'a str,
+    home_url: String,
+    bus_name: &'a str,
     current_url: Option<&'a str>,
     agent: &'a str,
     version: &'a str,
@@ -48,12 +104,24 @@ pub struct StateInfo<'a> {
     engine: Engine,
     debug: bool,
     state: State,
+    navigation_blocked: u64,
+    display: Option<DisplayState>,
+    idle: IdleInfo,
+    pending_action: Option<PendingInfo>,
+    connectivity: ConnectivityInfo,
+    user: Option<String>,
 }
 
 impl PanelInfo {
-    pub fn state_info<'a>(&'a self, state: State, current_url: Option<&'a str>) -> StateInfo<'a> {
+    pub fn state_info<'a>(
+        &'a self,
+        state: State,
+        current_url: Option<&'a str>,
+        display: Option<DisplayState>,
+    ) -> StateInfo<'a> {
         StateInfo {
-            home_url: &self.home_url,
+            home_url: crate::home_url(),
+            bus_name: &self.bus_name,
             current_url,
             agent: &self.agent,
             version: &self.version,
@@ -61,43 +129,113 @@ impl PanelInfo {
             engine: self.engine,
             debug: self.debug,
             state,
+            navigation_blocked: crate::NAVIGATION_BLOCKED.load(atomic::Ordering::Relaxed),
+            display,
+            idle: crate::idle::info(),
+            pending_action: crate::commands::pending(),
+            connectivity: crate::connectivity::info(),
+            user: crate::bridge::user(),
         }
     }
 }
 
-#[derive(Deserialize, Serialize, bmart::tools::EnumStr, Copy, Clone)]
+#[derive(Deserialize, Serialize, bmart::tools::EnumStr, Copy, Clone, Eq, PartialEq)]
 #[serde(rename_all = "lowercase")]
 pub enum Engine {
     Wasm,
     Js,
 }
 
-#[derive(Deserialize, Serialize, bmart::tools::EnumStr, Copy, Clone)]
+#[derive(Deserialize, Serialize, bmart::tools::EnumStr, Copy, Clone, Eq, PartialEq)]
 #[serde(rename_all = "lowercase")]
 pub enum BusMode {
-    #[cfg(target_os = "linux")]
+    /// supported on Linux only
     Server,
     Client,
 }
 
+impl BusMode {
+    fn default_name(self) -> &'static str {
+        match self {
+            BusMode::Server => ".panel",
+            BusMode::Client => "eva.panel.{hostname}",
+        }
+    }
+}
+
 impl Default for Engine {
     fn default() -> Self {
         Self::Wasm
     }
 }
 
-#[derive(Deserialize, Clone)]
+#[derive(Deserialize, Clone, Eq, PartialEq)]
+#[serde(deny_unknown_fields)]
 pub struct BusConfig {
     mode: BusMode,
+    #[serde(default = "default_bus_path")]
     path: String,
+    #[serde(default)]
+    name: Option<String>,
 }
 
+#[inline]
+fn default_bus_path() -> String {
+    "/tmp/evapanel.sock".to_owned()
+}
+
+/// the default socket path if the monitor is set in the config
+const DEFAULT_MONITOR_BUS_PATH: &str = "/tmp/evapanel.{monitor}.sock";
+
+const BUS_TEMPLATE_VARS: &[&str] = &["{hostname}", "{monitor}"];
+
 impl BusConfig {
     #[inline]
     pub fn path(&self) -> &str {
         &self.path
     }
     #[inline]
+    pub fn name(&self) -> &str {
+        self.name
+            .as_deref()
+            .unwrap_or_else(|| self.mode.default_name())
+    }
+    /// Expands template variables in the name and the path. If the monitor is set in the config,
+    /// ".{monitor}" is appended to the default name and the default path, to run multiple panels
+    /// on a single host
+    pub fn resolve(&mut self, monitor_set: bool) -> EResult<()> {
+        let hostname = hostname::get().map_err(Error::failed)?;
+        let hostname = hostname.to_string_lossy();
+        // monitor names may contain spaces (e.g. model names on Wayland)
+        let monitor: String = crate::MONITOR
+            .get()
+            .map_or("default", String::as_str)
+            .chars()
+            .map(|c| {
+                if c.is_ascii_alphanumeric() || c == '-' || c == '_' {
+                    c
+                } else {
+                    '_'
+                }
+            })
+            .collect();
+        let expand = |s: &str| {
+            s.replace("{hostname}", &hostname)
+                .replace("{monitor}", &monitor)
+        };
+        if monitor_set {
+            if self.name.is_none() {
+                self.name = Some(format!("{}.{{monitor}}", self.mode.default_name()));
+            }
+            if self.path == default_bus_path() {
+                DEFAULT_MONITOR_BUS_PATH.clone_into(&mut self.path);
+            }
+        }
+        self.name = Some(expand(self.name()));
+        self.path = expand(&self.path);
+        Ok(())
+    }
+    #[inline]
     pub fn mode(&self) -> BusMode {
         self.mode
     }
@@ -108,12 +246,67 @@ impl BusConfig {
             || self.path.ends_with(".socket")
             || self.path.ends_with(".ipc")
     }
+    /// Returns a list of (key, problem)
+    pub fn validate(&self) -> Vec<(&'static str, String)> {
+        let mut problems = Vec::new();
+        let name = BUS_TEMPLATE_VARS
+            .iter()
+            .fold(self.name().to_owned(), |name, var| name.replace(var, ""));
+        if self.name().is_empty() {
+            problems.push(("bus.name", "name is empty".to_owned()));
+        } else if name.contains('{') {
+            problems.push((
+                "bus.name",
+                format!(
+                    "unknown template variable, available: {}",
+                    BUS_TEMPLATE_VARS.join(", ")
+                ),
+            ));
+        }
+        #[cfg(not(target_os = "linux"))]
+        if self.mode == BusMode::Server {
+            problems.push((
+                "bus.mode",
+                "server mode is supported on Linux only".to_owned(),
+            ));
+        }
+        if self.path.is_empty() {
+            problems.push(("bus.path", "path is empty".to_owned()));
+        } else if self.is_unix_sock() {
+            if self.mode == BusMode::Server {
+                let dir = std::path::Path::new(&self.path)
+                    .parent()
+                    .filter(|p| !p.as_os_str().is_empty());
+                if let Some(dir) = dir {
+                    if !dir.is_dir() {
+                        problems.push((
+                            "bus.path",
+                            format!("socket directory {} does not exist", dir.display()),
+                        ));
+                    }
+                }
+            }
+        } else {
+            match self.path.rsplit_once(':') {
+                Some((host, port)) if !host.is_empty() && port.parse::<u16>().is_ok() => {}
+                _ => problems.push((
+                    "bus.path",
+                    format!(
+                        "{} is neither a UNIX socket (*.sock, *.socket, *.ipc) nor HOST:PORT",
+                        self.path
+                    ),
+                )),
+            }
+        }
+        problems
+    }
 }
 
 pub enum UEvent {
     Login(String, String),
     Logout,
     Eval(String),
+    EvalWithResult(String, async_channel::Sender<EResult<serde_json::Value>>),
     Zoom(f64),
     Navigate(Option<String>),
     Alert(String, AlertLevel, u16),
@@ -122,6 +315,126 @@ pub enum UEvent {
     CloseDevTools,
     GetState(async_channel::Sender<State>),
     GetLocation(async_channel::Sender<Option<String>>),
+    Screenshot(
+        ScreenshotOptions,
+        async_channel::Sender<EResult<Screenshot>>,
+    ),
+    ReloadConfig(Option<async_channel::Sender<EResult<ReloadReport>>>),
+    GetMonitors(async_channel::Sender<Vec<MonitorInfo>>),
+    /// the display is going to be blanked
+    Sleep,
+    /// shows the auto-logout countdown (seconds) or hides it
+    LogoutWarning(Option<u64>),
+    /// shows or hides the offline banner
+    Offline(bool),
+    /// reloads the current page
+    ReloadPage,
+    /// checks the renderer with the timeout, None if the hang watchdog has been disabled
+    Heartbeat(Option<Duration>),
+    /// a bridge call result for the page
+    BridgeReply(u64, Result<serde_json::Value, String>),
+    /// the page has been loaded
+    PageLoaded,
+}
+
+/// The monitor index (starting from 0) or name
+#[derive(Deserialize, Clone, Eq, PartialEq)]
+#[serde(untagged)]
+pub enum MonitorSelector {
+    Index(usize),
+    Name(String),
+}
+
+impl MonitorSelector {
+    pub fn matches(&self, index: usize, name: Option<&str>) -> bool {
+        match self {
+            MonitorSelector::Index(i) => *i == index,
+            MonitorSelector::Name(n) => name == Some(n.as_str()),
+        }
+    }
+}
+
+impl fmt::Display for MonitorSelector {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MonitorSelector::Index(i) => write!(f, "#{}", i),
+            MonitorSelector::Name(n) => write!(f, "{}", n),
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct MonitorInfo {
+    index: usize,
+    name: Option<String>,
+    /// physical size
+    width: u32,
+    height: u32,
+    x: i32,
+    y: i32,
+    scale_factor: f64,
+    /// the window is on the monitor
+    current: bool,
+}
+
+impl MonitorInfo {
+    pub fn new(index: usize, monitor: &tao::monitor::MonitorHandle, current: bool) -> Self {
+        let size = monitor.size();
+        let position = monitor.position();
+        Self {
+            index,
+            name: monitor.name(),
+            width: size.width,
+            height: size.height,
+            x: position.x,
+            y: position.y,
+            scale_factor: monitor.scale_factor(),
+            current,
+        }
+    }
+}
+
+#[derive(Deserialize, Copy, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct ScreenshotOptions {
+    #[serde(default = "default_screenshot_scale")]
+    pub(crate) scale: f64,
+    #[serde(default)]
+    pub(crate) full: bool,
+    /// JPEG quality (1..100), the image is encoded as PNG if not set
+    #[serde(default)]
+    pub(crate) quality: Option<u8>,
+}
+
+impl Default for ScreenshotOptions {
+    fn default() -> Self {
+        Self {
+            scale: default_screenshot_scale(),
+            full: false,
+            quality: None,
+        }
+    }
+}
+
+#[inline]
+fn default_screenshot_scale() -> f64 {
+    1.0
+}
+
+#[derive(Serialize)]
+pub struct Screenshot {
+    pub(crate) format: &'static str,
+    pub(crate) width: i32,
+    pub(crate) height: i32,
+    #[serde(serialize_with = "serialize_bytes")]
+    pub(crate) data: Vec<u8>,
+}
+
+fn serialize_bytes<S>(data: &[u8], serializer: S) -> Result<S::Ok, S::Error>
+where
+    S: Serializer,
+{
+    serializer.serialize_bytes(data)
 }
 
 #[derive(Deserialize, bmart::tools::EnumStr)]
@@ -151,15 +464,58 @@ where
 }
 
 pub async fn system_cmd_x<I, S>(cmd: &str, args: I, exit_ok: &[i32]) -> EResult<()>
+where
+    I: IntoIterator<Item = S>,
+    S: AsRef<OsStr>,
+{
+    system_cmd_output_x(cmd, args, CMD_TIMEOUT, exit_ok)
+        .await
+        .map(|_| ())
+}
+
+/// Runs the command with a custom timeout
+pub async fn system_cmd_t<I, S>(
+    cmd: &str,
+    args: I,
+    timeout: Duration,
+    exit_ok: &[i32],
+) -> EResult<()>
+where
+    I: IntoIterator<Item = S>,
+    S: AsRef<OsStr>,
+{
+    system_cmd_output_x(cmd, args, timeout, exit_ok)
+        .await
+        .map(|_| ())
+}
+
+/// Returns the command stdout lines
+pub fn system_cmd_output<'a, I, S>(
+    cmd: &'a str,
+    args: I,
+) -> impl Future<Output = EResult<Vec<String>>> + 'a
+where
+    I: IntoIterator<Item = S> + 'a,
+    S: AsRef<OsStr> + 'a,
+{
+    system_cmd_output_x(cmd, args, CMD_TIMEOUT, &[])
+}
+
+async fn system_cmd_output_x<I, S>(
+    cmd: &str,
+    args: I,
+    timeout: Duration,
+    exit_ok: &[i32],
+) -> EResult<Vec<String>>
 where
     I: IntoIterator<Item = S>,
     S: AsRef<OsStr>,
 {
     let res =
-        bmart::process::command(cmd, args, CMD_TIMEOUT, bmart::process::Options::default()).await?;
+        bmart::process::command(cmd, args, timeout, bmart::process::Options::default()).await?;
     let code = res.code.unwrap_or(-1);
     if code == 0 || exit_ok.contains(&code) {
-        Ok(())
+        Ok(res.out)
     } else {
         Err(Error::failed(format!(
             "process exit code {}\n{}",
@@ -0,0 +1,352 @@
+use crate::acl::Acl;
+use crate::bridge::BridgeConfig;
+use crate::commands::Commands;
+use crate::common::{BusConfig, Engine, MonitorSelector, UEvent};
+use crate::connectivity::ConnectivityConfig;
+use crate::display::DisplayConfig;
+use crate::hang::HangWatchdogConfig;
+use crate::idle::{AutoLogoutConfig, IdleConfig};
+use crate::schedule::ScheduleRule;
+use eva_common::{EResult, Error};
+use log::{debug, error};
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::time::{Duration, SystemTime};
+use tao::event_loop::EventLoopProxy;
+use url::Url;
+
+const WATCH_INTERVAL: Duration = Duration::from_secs(2);
+pub(crate) const ZOOM_MIN: f64 = 0.1;
+pub(crate) const ZOOM_MAX: f64 = 10.0;
+
+/// Checks a runtime zoom level (RPC or bridge call)
+pub(crate) fn check_zoom(level: f64) -> EResult<()> {
+    if (ZOOM_MIN..=ZOOM_MAX).contains(&level) {
+        Ok(())
+    } else {
+        Err(Error::invalid_params(format!(
+            "zoom level {} is out of range {}..{}",
+            level, ZOOM_MIN, ZOOM_MAX
+        )))
+    }
+}
+
+#[inline]
+fn default_title() -> String {
+    "EVA ICS Panel".to_owned()
+}
+
+#[inline]
+fn default_home_url() -> String {
+    "http://eva/ui/".to_owned()
+}
+
+fn default_window_size() -> (u32, u32) {
+    (800, 500)
+}
+
+#[inline]
+fn default_zoom() -> f64 {
+    1.0
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    #[serde(default = "default_title")]
+    pub(crate) title: String,
+    #[serde(default = "default_home_url")]
+    pub(crate) home_url: String,
+    #[serde(default = "default_zoom")]
+    pub(crate) zoom: f64,
+    #[serde(default)]
+    pub(crate) engine: Engine,
+    #[serde(default)]
+    pub(crate) allowed_urls: HashSet<String>,
+    #[serde(default)]
+    pub(crate) denied_urls: HashSet<String>,
+    #[serde(default)]
+    pub(crate) fullscreen: bool,
+    #[serde(default = "default_window_size")]
+    pub(crate) window_size: (u32, u32),
+    #[serde(default)]
+    pub(crate) monitor: Option<MonitorSelector>,
+    #[serde(default)]
+    pub(crate) show_cursor: bool,
+    #[serde(default)]
+    pub(crate) debug: bool,
+    #[serde(default)]
+    pub(crate) sig: Option<String>,
+    #[serde(default)]
+    pub(crate) bus: Option<BusConfig>,
+    #[serde(default)]
+    pub(crate) display: DisplayConfig,
+    #[serde(default)]
+    pub(crate) display_schedule: Vec<ScheduleRule>,
+    #[serde(default)]
+    pub(crate) idle: Option<IdleConfig>,
+    #[serde(default)]
+    pub(crate) auto_logout: Option<AutoLogoutConfig>,
+    #[serde(default)]
+    pub(crate) connectivity: Option<ConnectivityConfig>,
+    #[serde(default)]
+    pub(crate) hang_watchdog: Option<HangWatchdogConfig>,
+    #[serde(default)]
+    pub(crate) bridge: Option<BridgeConfig>,
+    #[serde(default)]
+    pub(crate) acl: Option<Acl>,
+    #[serde(default)]
+    pub(crate) commands: Commands,
+    /// a local directory, served as evapanel://local/
+    #[serde(default)]
+    pub(crate) pages_dir: Option<String>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            title: default_title(),
+            home_url: default_home_url(),
+            zoom: default_zoom(),
+            engine: <_>::default(),
+            allowed_urls: HashSet::new(),
+            denied_urls: HashSet::new(),
+            fullscreen: false,
+            window_size: default_window_size(),
+            monitor: None,
+            show_cursor: false,
+            debug: false,
+            sig: None,
+            bus: None,
+            display: <_>::default(),
+            display_schedule: Vec::new(),
+            idle: None,
+            auto_logout: None,
+            connectivity: None,
+            hang_watchdog: None,
+            bridge: None,
+            acl: None,
+            commands: <_>::default(),
+            pages_dir: None,
+        }
+    }
+}
+
+impl Config {
+    /// Loads the config, returns the default one if the config file is not found (the second
+    /// tuple field is set to true)
+    pub fn load(path: &str) -> EResult<(Self, bool)> {
+        match std::fs::read(shellexpand::tilde(path).as_ref()) {
+            Ok(v) => Ok((
+                serde_yaml::from_slice(&v)
+                    .map_err(|e| Error::invalid_data(format!("Unable to parse {}: {}", path, e)))?,
+                false,
+            )),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok((Config::default(), true)),
+            Err(e) => Err(Error::io(format!("Unable to open {}: {}", path, e))),
+        }
+    }
+    /// Validates the config semantics, returns a list of (key, problem)
+    pub fn validate(&self) -> Vec<(&'static str, String)> {
+        let mut problems = Vec::new();
+        match Url::parse(&self.home_url) {
+            Ok(url) if url.has_host() || url.scheme() == "file" => {}
+            Ok(_) => problems.push(("home_url", format!("{} has no host", self.home_url))),
+            Err(e) => problems.push(("home_url", format!("{}: {}", self.home_url, e))),
+        }
+        if !(ZOOM_MIN..=ZOOM_MAX).contains(&self.zoom) {
+            problems.push((
+                "zoom",
+                format!("{} is out of range {}..{}", self.zoom, ZOOM_MIN, ZOOM_MAX),
+            ));
+        }
+        if !self.fullscreen && (self.window_size.0 == 0 || self.window_size.1 == 0) {
+            problems.push((
+                "window_size",
+                "width and height must be non-zero".to_owned(),
+            ));
+        }
+        if let Err(e) = crate::policy::check_patterns(&self.allowed_urls) {
+            problems.push(("allowed_urls", e.to_string()));
+        }
+        if let Err(e) = crate::policy::check_patterns(&self.denied_urls) {
+            problems.push(("denied_urls", e.to_string()));
+        }
+        if let Some(ref bus) = self.bus {
+            problems.extend(bus.validate());
+        }
+        problems.extend(self.display.validate());
+        problems.extend(crate::schedule::validate(&self.display_schedule));
+        if let Some(ref idle) = self.idle {
+            problems.extend(idle.validate());
+        }
+        if let Some(ref auto_logout) = self.auto_logout {
+            problems.extend(auto_logout.validate());
+        }
+        if let Some(ref connectivity) = self.connectivity {
+            problems.extend(connectivity.validate());
+        }
+        if let Some(ref hang_watchdog) = self.hang_watchdog {
+            problems.extend(hang_watchdog.validate());
+        }
+        if let Some(ref bridge) = self.bridge {
+            problems.extend(bridge.validate());
+        }
+        problems.extend(self.commands.validate());
+        if let Some(problem) = self
+            .pages_dir
+            .as_deref()
+            .and_then(crate::protocol::check_pages_dir)
+        {
+            problems.push(("pages_dir", problem));
+        }
+        problems
+    }
+}
+
+/// Finds the line number of a (dot-separated) key in a YAML source, block mappings only
+fn key_line(source: &str, key: &str) -> Option<usize> {
+    let mut segments = key.split('.');
+    let mut segment = segments.next()?;
+    let mut parent_indent: Option<usize> = None;
+    for (n, line) in source.lines().enumerate() {
+        let trimmed = line.trim_start();
+        if trimmed.is_empty() || trimmed.starts_with('#') {
+            continue;
+        }
+        let indent = line.len() - trimmed.len();
+        match parent_indent {
+            Some(i) if indent <= i => return None,
+            None if indent > 0 => continue,
+            _ => {}
+        }
+        if trimmed
+            .strip_prefix(segment)
+            .is_some_and(|rest| rest.trim_start().starts_with(':'))
+        {
+            if let Some(next) = segments.next() {
+                segment = next;
+                parent_indent = Some(indent);
+            } else {
+                return Some(n + 1);
+            }
+        }
+    }
+    None
+}
+
+/// Parses and validates the config file, prints the problems found
+pub fn check(path: &str) -> EResult<()> {
+    let source = std::fs::read_to_string(shellexpand::tilde(path).as_ref())
+        .map_err(|e| Error::io(format!("Unable to open {}: {}", path, e)))?;
+    let config: Config = serde_yaml::from_str(&source)
+        .map_err(|e| Error::invalid_data(format!("Unable to parse {}: {}", path, e)))?;
+    let problems = config.validate();
+    for (key, problem) in &problems {
+        if let Some(line) = key_line(&source, key) {
+            eprintln!("{}: line {}: {}: {}", path, line, key, problem);
+        } else {
+            eprintln!("{}: {}: {}", path, key, problem);
+        }
+    }
+    if problems.is_empty() {
+        println!("{}: OK", path);
+        Ok(())
+    } else {
+        Err(Error::invalid_data(format!(
+            "{} problem(s) found in {}",
+            problems.len(),
+            path
+        )))
+    }
+}
+
+/// Config reload result
+#[derive(Serialize, Default)]
+pub struct ReloadReport {
+    /// settings, applied live
+    pub(crate) applied: Vec<&'static str>,
+    /// settings, changed in the config file but require the process restart
+    pub(crate) restart_required: Vec<&'static str>,
+}
+
+#[inline]
+fn modified(path: &str) -> Option<SystemTime> {
+    std::fs::metadata(shellexpand::tilde(path).as_ref())
+        .and_then(|m| m.modified())
+        .ok()
+}
+
+/// Watches the config file for modifications and asks the event loop to reload it
+pub fn watch(path: &str, proxy: &EventLoopProxy<UEvent>) {
+    let mut last_modified = modified(path);
+    loop {
+        std::thread::sleep(WATCH_INTERVAL);
+        let file_modified = modified(path);
+        if file_modified != last_modified {
+            last_modified = file_modified;
+            if file_modified.is_some() {
+                debug!("config file {} modified", path);
+                if proxy.send_event(UEvent::ReloadConfig(None)).is_err() {
+                    error!("unable to send config reload event");
+                    break;
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SOURCE: &str = "# home_url: http://commented/
+title: Panel
+home_url: http://eva/ # zoom: 2
+display:
+  # backend: sysfs
+  backend: xrandr
+
+  rotation: left
+idle:
+  dim_after: 10
+bus:
+  mode: client
+display_schedule: []
+";
+
+    #[test]
+    fn test_key_line() {
+        assert_eq!(key_line(SOURCE, "title"), Some(2));
+        assert_eq!(key_line(SOURCE, "display_schedule"), Some(13));
+        assert_eq!(key_line(SOURCE, "display"), Some(4));
+    }
+
+    #[test]
+    fn test_key_line_nested() {
+        assert_eq!(key_line(SOURCE, "display.backend"), Some(6));
+        assert_eq!(key_line(SOURCE, "display.rotation"), Some(8));
+        assert_eq!(key_line(SOURCE, "idle.dim_after"), Some(10));
+        assert_eq!(key_line(SOURCE, "bus.mode"), Some(12));
+    }
+
+    #[test]
+    fn test_key_line_comments() {
+        // the commented key is skipped
+        assert_eq!(key_line(SOURCE, "home_url"), Some(3));
+        // keys in comments only
+        assert_eq!(key_line(SOURCE, "zoom"), None);
+    }
+
+    #[test]
+    fn test_key_line_missing() {
+        assert_eq!(key_line(SOURCE, "sig"), None);
+        assert_eq!(key_line(SOURCE, "idle.blank_after"), None);
+        // nested keys are not matched at the top level
+        assert_eq!(key_line(SOURCE, "backend"), None);
+        // the key exists under another parent only
+        assert_eq!(key_line(SOURCE, "display.mode"), None);
+        assert_eq!(key_line(SOURCE, "display.backend.x"), None);
+        assert_eq!(key_line("", "title"), None);
+    }
+}
@@ -0,0 +1,257 @@
+use crate::common::{PanelEvent, UEvent};
+use log::{debug, info, warn};
+use serde::{Deserialize, Serialize};
+use std::sync::{Mutex, RwLock};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tao::event_loop::EventLoopProxy;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use url::Url;
+
+/// the check interval if the watchdog is disabled
+const IDLE_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Shows or hides the "connection lost" banner, the pointer input is not blocked
+pub const OFFLINE_JS: &str = r"(() => {
+    let banner = null;
+    window.__evapanel_offline = (offline) => {
+      if (!offline) {
+        if (banner) {
+          banner.remove();
+          banner = null;
+        }
+        return;
+      }
+      if (banner && banner.isConnected) return;
+      banner = document.createElement('div');
+      banner.style.cssText = 'position: fixed; left: 50%; bottom: 2vmin; ' +
+        'transform: translateX(-50%); z-index: 2147483647; pointer-events: none; ' +
+        'padding: 0.5em 1.2em; border-radius: 1em; background: rgba(0, 0, 0, 0.7); ' +
+        'color: #f28b82; font: 2.5vmin sans-serif';
+      banner.textContent = 'Connection lost';
+      document.documentElement.appendChild(banner);
+    };
+  })();";
+
+static CONFIG: RwLock<Option<ConnectivityConfig>> = RwLock::new(None);
+static STATUS: Mutex<Option<Status>> = Mutex::new(None);
+
+#[derive(Deserialize, Copy, Clone, Default, Eq, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Probe {
+    /// TCP connect to the home URL origin
+    #[default]
+    Tcp,
+    /// HTTP HEAD request, any HTTP response means the server is online. HTTPS origins are
+    /// probed with TCP connect
+    Http,
+}
+
+#[inline]
+fn default_interval() -> f64 {
+    5.0
+}
+
+#[inline]
+fn default_timeout() -> f64 {
+    3.0
+}
+
+#[inline]
+fn default_failures() -> u32 {
+    2
+}
+
+/// Times are in seconds
+#[derive(Deserialize, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct ConnectivityConfig {
+    #[serde(default = "default_interval")]
+    interval: f64,
+    #[serde(default = "default_timeout")]
+    timeout: f64,
+    #[serde(default)]
+    probe: Probe,
+    /// consecutive failed probes, after which the server is considered offline
+    #[serde(default = "default_failures")]
+    failures: u32,
+}
+
+impl ConnectivityConfig {
+    /// Returns a list of (key, problem)
+    pub fn validate(&self) -> Vec<(&'static str, String)> {
+        let mut problems = Vec::new();
+        if self.interval <= 0.0 || !self.interval.is_finite() {
+            problems.push(("connectivity.interval", "must be positive".to_owned()));
+        }
+        if self.timeout <= 0.0 || !self.timeout.is_finite() {
+            problems.push(("connectivity.timeout", "must be positive".to_owned()));
+        }
+        if self.failures == 0 {
+            problems.push(("connectivity.failures", "must be positive".to_owned()));
+        }
+        problems
+    }
+}
+
+#[derive(Copy, Clone)]
+struct Status {
+    online: bool,
+    changed: SystemTime,
+}
+
+#[derive(Serialize, Default)]
+pub struct ConnectivityInfo {
+    /// not set if the watchdog is disabled or the server has not been probed yet
+    online: Option<bool>,
+    /// the last status change, UNIX timestamp
+    changed: Option<f64>,
+}
+
+pub fn set(config: Option<ConnectivityConfig>) {
+    *CONFIG.write().unwrap() = config;
+}
+
+pub fn info() -> ConnectivityInfo {
+    STATUS
+        .lock()
+        .unwrap()
+        .map_or_else(ConnectivityInfo::default, |s| ConnectivityInfo {
+            online: Some(s.online),
+            changed: s
+                .changed
+                .duration_since(UNIX_EPOCH)
+                .ok()
+                .map(|d| d.as_secs_f64()),
+        })
+}
+
+pub fn offline_js(offline: bool) -> String {
+    format!(
+        "window.__evapanel_offline && window.__evapanel_offline({})",
+        offline
+    )
+}
+
+async fn http_head(stream: &mut TcpStream, url: &Url) -> std::io::Result<bool> {
+    let host = url.host_str().unwrap_or_default();
+    let host_header = url
+        .port()
+        .map_or_else(|| host.to_owned(), |port| format!("{}:{}", host, port));
+    let request = format!(
+        "HEAD / HTTP/1.0\r\nHost: {}\r\nUser-Agent: {}/{}\r\nConnection: close\r\n\r\n",
+        host_header,
+        crate::AGENT_NAME,
+        crate::VERSION
+    );
+    stream.write_all(request.as_bytes()).await?;
+    let mut buf = [0_u8; 5];
+    stream.read_exact(&mut buf).await?;
+    Ok(&buf == b"HTTP/")
+}
+
+/// Returns None if the home URL has no network origin (e.g. an internal page)
+async fn probe(home_url: &str, config: &ConnectivityConfig) -> Option<bool> {
+    let url = Url::parse(home_url).ok()?;
+    let host = url.host_str()?;
+    let port = url.port_or_known_default()?;
+    let timeout = Duration::try_from_secs_f64(config.timeout).unwrap_or_default();
+    let http = config.probe == Probe::Http && url.scheme() == "http";
+    let result = tokio::time::timeout(timeout, async {
+        let mut stream = TcpStream::connect(format!("{}:{}", host, port)).await?;
+        if http {
+            http_head(&mut stream, &url).await
+        } else {
+            Ok(true)
+        }
+    })
+    .await;
+    let online = match result {
+        Ok(Ok(v)) => v,
+        Ok(Err(e)) => {
+            debug!("connectivity probe {}:{} failed: {}", host, port, e);
+            false
+        }
+        Err(_) => {
+            debug!("connectivity probe {}:{} timed out", host, port);
+            false
+        }
+    };
+    Some(online)
+}
+
+/// Sets the status, returns the previous one if changed
+fn set_status(online: Option<bool>) -> Option<Option<bool>> {
+    let mut status = STATUS.lock().unwrap();
+    let prev = status.map(|s| s.online);
+    if prev == online {
+        return None;
+    }
+    *status = online.map(|online| Status {
+        online,
+        changed: SystemTime::now(),
+    });
+    Some(prev)
+}
+
+/// Probes the home URL origin, displays the banner while the server is offline and reloads the
+/// page when it is back
+pub async fn run(proxy: EventLoopProxy<UEvent>) {
+    let mut failures = 0;
+    macro_rules! send_event {
+        ($event: expr) => {
+            if proxy.send_event($event).is_err() {
+                break;
+            }
+        };
+    }
+    loop {
+        let config = CONFIG.read().unwrap().clone();
+        let Some(config) = config else {
+            failures = 0;
+            if set_status(None) == Some(Some(false)) {
+                send_event!(UEvent::Offline(false));
+            }
+            tokio::time::sleep(IDLE_INTERVAL).await;
+            continue;
+        };
+        let online = match probe(&crate::home_url(), &config).await {
+            Some(true) => {
+                failures = 0;
+                Some(true)
+            }
+            Some(false) => {
+                failures += 1;
+                if failures >= config.failures {
+                    Some(false)
+                } else {
+                    // keeps the current status until the failure threshold is reached
+                    STATUS.lock().unwrap().map(|s| s.online)
+                }
+            }
+            None => None,
+        };
+        if let Some(prev) = set_status(online) {
+            match online {
+                Some(true) => info!("HMI server is online"),
+                Some(false) => warn!("HMI server is offline"),
+                None => {}
+            }
+            if let Some(online) = online {
+                crate::eapi::notify(PanelEvent::Connectivity { online });
+            }
+            if prev == Some(false) {
+                send_event!(UEvent::Offline(false));
+                if online == Some(true) {
+                    send_event!(UEvent::ReloadPage);
+                }
+            }
+        }
+        // the banner is re-sent while offline, as a new page may be loaded
+        if online == Some(false) {
+            send_event!(UEvent::Offline(true));
+        }
+        tokio::time::sleep(Duration::try_from_secs_f64(config.interval).unwrap_or(IDLE_INTERVAL))
+            .await;
+    }
+}
@@ -0,0 +1,791 @@
+use crate::commands::{self, Action};
+use crate::common::{system_cmd, system_cmd_output, system_cmd_x};
+use eva_common::{err_logger, EResult, Error};
+use log::{debug, info, warn};
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+
+/// bl_power values, see FB_BLANK_* in linux/fb.h
+const BL_POWER_ON: &str = "0";
+const BL_POWER_OFF: &str = "4";
+
+static BACKEND: RwLock<Option<Arc<dyn Backend>>> = RwLock::new(None);
+/// the configured rotation or the last one set via RPC
+static ROTATION: RwLock<Option<Rotation>> = RwLock::new(None);
+
+err_logger!();
+
+#[derive(Deserialize, bmart::tools::EnumStr, Copy, Clone, Default, Eq, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum BackendKind {
+    /// wayland if WAYLAND_DISPLAY is set, xrandr otherwise
+    #[default]
+    Auto,
+    /// xrandr for power, xbacklight for brightness
+    Xrandr,
+    /// xset DPMS for power, xbacklight for brightness
+    Dpms,
+    /// /sys/class/backlight device, bl_power for power
+    Sysfs,
+    /// wlopm for power, wlr-randr for state, sysfs backlight (if available) for brightness
+    Wayland,
+}
+
+impl BackendKind {
+    fn resolve(self) -> Self {
+        if self == BackendKind::Auto {
+            if std::env::var_os("WAYLAND_DISPLAY").is_some() {
+                BackendKind::Wayland
+            } else {
+                BackendKind::Xrandr
+            }
+        } else {
+            self
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize, bmart::tools::EnumStr, Copy, Clone, Eq, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Rotation {
+    Normal,
+    Left,
+    Right,
+    Inverted,
+}
+
+impl Rotation {
+    /// wl_output transform (counter-clockwise)
+    fn wayland_transform(self) -> &'static str {
+        match self {
+            Rotation::Normal => "normal",
+            Rotation::Left => "90",
+            Rotation::Inverted => "180",
+            Rotation::Right => "270",
+        }
+    }
+}
+
+#[inline]
+fn default_backlight_dir() -> String {
+    "/sys/class/backlight".to_owned()
+}
+
+#[derive(Deserialize, Clone, Eq, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct DisplayConfig {
+    #[serde(default)]
+    backend: BackendKind,
+    #[serde(default = "default_backlight_dir")]
+    backlight_dir: String,
+    /// sysfs backlight device, the first one found is used if not set
+    #[serde(default)]
+    backlight: Option<String>,
+    #[serde(default)]
+    rotation: Option<Rotation>,
+    /// xinput touchscreen device name, all touch devices are mapped if not set
+    #[serde(default)]
+    touchscreen: Option<String>,
+}
+
+impl Default for DisplayConfig {
+    fn default() -> Self {
+        Self {
+            backend: BackendKind::default(),
+            backlight_dir: default_backlight_dir(),
+            backlight: None,
+            rotation: None,
+            touchscreen: None,
+        }
+    }
+}
+
+impl DisplayConfig {
+    #[inline]
+    pub fn rotation(&self) -> Option<Rotation> {
+        self.rotation
+    }
+    /// Returns a list of (key, problem)
+    pub fn validate(&self) -> Vec<(&'static str, String)> {
+        let mut problems = Vec::new();
+        if self.backend == BackendKind::Sysfs {
+            if let Err(e) = backlight_device(&self.backlight_dir, self.backlight.as_deref()) {
+                problems.push(("display.backlight_dir", e.to_string()));
+            }
+        }
+        problems
+    }
+}
+
+/// The actual display state, the fields are not set if the backend is unable to get them
+#[derive(Serialize, Default)]
+pub struct DisplayState {
+    monitor: Option<String>,
+    power: Option<bool>,
+    brightness: Option<f32>,
+    resolution: Option<(u32, u32)>,
+    /// the rotation set by the panel
+    rotation: Option<Rotation>,
+}
+
+impl DisplayState {
+    #[inline]
+    pub fn brightness(&self) -> Option<f32> {
+        self.brightness
+    }
+}
+
+#[async_trait::async_trait]
+pub trait Backend: Send + Sync {
+    async fn set_power(&self, on: bool) -> EResult<()>;
+    /// brightness is set in percents
+    async fn set_brightness(&self, brightness: f32) -> EResult<()>;
+    async fn state(&self) -> EResult<DisplayState>;
+    async fn set_rotation(&self, _rotation: Rotation) -> EResult<()> {
+        Err(Error::unsupported(
+            "rotation is not supported by the display backend",
+        ))
+    }
+}
+
+/// Logs the error and returns None
+fn state_field<T>(name: &str, value: EResult<T>) -> Option<T> {
+    value
+        .map_err(|e| debug!("unable to get display {}: {}", name, e))
+        .ok()
+}
+
+fn parse_resolution(s: &str, delimiter: char) -> Option<(u32, u32)> {
+    let (width, height) = s.split_once(delimiter)?;
+    Some((width.parse().ok()?, height.parse().ok()?))
+}
+
+/// Returns (power, resolution) of the monitor from "xrandr --query" output
+async fn xrandr_state(monitor: &str) -> EResult<(bool, Option<(u32, u32)>)> {
+    let out = system_cmd_output("xrandr", vec!["--query"]).await?;
+    for line in out {
+        let mut fields = line.split_whitespace();
+        if fields.next() != Some(monitor) {
+            continue;
+        }
+        if fields.next() != Some("connected") {
+            return Ok((false, None));
+        }
+        // an active output has the current mode, e.g. 1920x1080+0+0
+        let resolution = fields
+            .take(2)
+            .find_map(|f| parse_resolution(f.split('+').next().unwrap_or_default(), 'x'));
+        return Ok((resolution.is_some(), resolution));
+    }
+    Err(Error::not_found(format!("output {} not found", monitor)))
+}
+
+async fn xbacklight_get() -> EResult<f32> {
+    system_cmd_output("xbacklight", vec!["-get"])
+        .await?
+        .first()
+        .and_then(|v| v.trim().parse().ok())
+        .ok_or_else(|| Error::invalid_data("unable to parse xbacklight output"))
+}
+
+#[inline]
+fn monitor() -> EResult<&'static str> {
+    crate::MONITOR
+        .get()
+        .map(String::as_str)
+        .ok_or_else(|| Error::failed("monitor not detected"))
+}
+
+struct Xrandr {
+    touchscreen: Option<String>,
+}
+
+#[async_trait::async_trait]
+impl Backend for Xrandr {
+    async fn set_power(&self, on: bool) -> EResult<()> {
+        let monitor = monitor()?;
+        if on {
+            // turning the output off first helps to wake up some displays
+            xrandr_output(monitor, false).await?;
+            tokio::time::sleep(Duration::from_millis(500)).await;
+        }
+        xrandr_output(monitor, on).await
+    }
+    async fn set_brightness(&self, brightness: f32) -> EResult<()> {
+        xbacklight(brightness).await
+    }
+    async fn state(&self) -> EResult<DisplayState> {
+        let (power, resolution) = xrandr_state(monitor()?).await?;
+        Ok(DisplayState {
+            power: Some(power),
+            resolution,
+            brightness: state_field("brightness", xbacklight_get().await),
+            ..DisplayState::default()
+        })
+    }
+    async fn set_rotation(&self, rotation: Rotation) -> EResult<()> {
+        xrandr_rotate(monitor()?, rotation, self.touchscreen.as_deref()).await
+    }
+}
+
+async fn xrandr_output(monitor: &str, on: bool) -> EResult<()> {
+    // the output is enabled with the normal rotation, the current one must be set explicitly
+    let rotation = rotation().map(|r| r.to_string());
+    let mut args = vec!["--output", monitor, if on { "--auto" } else { "--off" }];
+    if let (true, Some(rotation)) = (on, rotation.as_deref()) {
+        args.extend(["--rotate", rotation]);
+    }
+    info!("setting display {} on={}", monitor, on);
+    if on {
+        system_cmd("xrandr", args).await
+    } else {
+        // xrandr exit code 1 is ok for off
+        system_cmd_x("xrandr", args, &[1]).await
+    }
+}
+
+async fn xrandr_rotate(
+    monitor: &str,
+    rotation: Rotation,
+    touchscreen: Option<&str>,
+) -> EResult<()> {
+    info!("setting display {} rotation={}", monitor, rotation);
+    let rotation = rotation.to_string();
+    system_cmd(
+        "xrandr",
+        vec!["--output", monitor, "--rotate", rotation.as_str()],
+    )
+    .await?;
+    map_touchscreens(monitor, touchscreen).await
+}
+
+/// Returns (id, name) of touch pointer devices from "xinput list" output
+fn touch_devices(out: &[String], name: Option<&str>) -> Vec<(String, String)> {
+    out.iter()
+        .filter(|line| line.contains("pointer"))
+        .filter_map(|line| {
+            let (name_part, rest) = line.split_once("id=")?;
+            let id: String = rest.chars().take_while(char::is_ascii_digit).collect();
+            let device = name_part
+                .trim_start_matches(|c: char| !c.is_alphanumeric())
+                .trim_end();
+            Some((id, device.to_owned()))
+        })
+        .filter(|(id, device)| {
+            !id.is_empty()
+                && name.map_or_else(
+                    || {
+                        let device = device.to_lowercase();
+                        device.contains("touch") && !device.contains("touchpad")
+                    },
+                    |n| device == n,
+                )
+        })
+        .collect()
+}
+
+/// Maps touchscreens to the output, xinput calculates the coordinate transformation matrix
+/// for the output position and rotation
+async fn map_touchscreens(monitor: &str, touchscreen: Option<&str>) -> EResult<()> {
+    let out = system_cmd_output("xinput", vec!["list"]).await?;
+    let devices = touch_devices(&out, touchscreen);
+    if devices.is_empty() {
+        debug!("no touchscreen devices found");
+    }
+    for (id, name) in devices {
+        info!("mapping touchscreen {} to {}", name, monitor);
+        system_cmd("xinput", vec!["map-to-output", id.as_str(), monitor]).await?;
+    }
+    Ok(())
+}
+
+async fn xbacklight(brightness: f32) -> EResult<()> {
+    let br_str = brightness.to_string();
+    system_cmd("xbacklight", vec!["-set", &br_str]).await
+}
+
+struct Dpms {
+    touchscreen: Option<String>,
+}
+
+#[async_trait::async_trait]
+impl Backend for Dpms {
+    async fn set_power(&self, on: bool) -> EResult<()> {
+        info!("setting display DPMS on={}", on);
+        system_cmd("xset", vec!["dpms", "force", if on { "on" } else { "off" }]).await
+    }
+    async fn set_brightness(&self, brightness: f32) -> EResult<()> {
+        xbacklight(brightness).await
+    }
+    async fn state(&self) -> EResult<DisplayState> {
+        let power = system_cmd_output("xset", vec!["q"])
+            .await?
+            .iter()
+            .find_map(|line| {
+                line.trim()
+                    .strip_prefix("Monitor is ")
+                    .map(|state| state == "On")
+            });
+        let resolution = match monitor() {
+            Ok(monitor) => state_field("resolution", xrandr_state(monitor).await)
+                .and_then(|(_, resolution)| resolution),
+            Err(_) => None,
+        };
+        Ok(DisplayState {
+            power,
+            resolution,
+            brightness: state_field("brightness", xbacklight_get().await),
+            ..DisplayState::default()
+        })
+    }
+    async fn set_rotation(&self, rotation: Rotation) -> EResult<()> {
+        xrandr_rotate(monitor()?, rotation, self.touchscreen.as_deref()).await
+    }
+}
+
+struct Sysfs {
+    dir: PathBuf,
+}
+
+impl Sysfs {
+    async fn read_value(&self, name: &str) -> EResult<u32> {
+        let path = self.dir.join(name);
+        tokio::fs::read_to_string(&path)
+            .await
+            .map_err(|e| Error::io(format!("Unable to read {}: {}", path.display(), e)))?
+            .trim()
+            .parse()
+            .map_err(|e| Error::invalid_data(format!("{}: {}", path.display(), e)))
+    }
+    async fn write_value(&self, name: &str, value: &str) -> EResult<()> {
+        let path = self.dir.join(name);
+        tokio::fs::write(&path, value)
+            .await
+            .map_err(|e| Error::io(format!("Unable to write {}: {}", path.display(), e)))
+    }
+    /// Returns the actual brightness in percents
+    #[allow(clippy::cast_possible_truncation)]
+    async fn brightness(&self) -> EResult<f32> {
+        let max = self.read_value("max_brightness").await?;
+        if max == 0 {
+            return Err(Error::invalid_data("max_brightness is zero"));
+        }
+        let value = self.read_value("actual_brightness").await?;
+        Ok((f64::from(value) * 100.0 / f64::from(max)) as f32)
+    }
+}
+
+#[async_trait::async_trait]
+impl Backend for Sysfs {
+    async fn set_power(&self, on: bool) -> EResult<()> {
+        info!("setting display {} on={}", self.dir.display(), on);
+        self.write_value("bl_power", if on { BL_POWER_ON } else { BL_POWER_OFF })
+            .await
+    }
+    #[allow(clippy::cast_possible_truncation)]
+    #[allow(clippy::cast_sign_loss)]
+    async fn set_brightness(&self, brightness: f32) -> EResult<()> {
+        let max = self.read_value("max_brightness").await?;
+        let value = (f64::from(max) * f64::from(brightness) / 100.0).round() as u32;
+        self.write_value("brightness", &value.to_string()).await
+    }
+    async fn state(&self) -> EResult<DisplayState> {
+        Ok(DisplayState {
+            power: state_field("power", self.read_value("bl_power").await.map(|v| v == 0)),
+            brightness: state_field("brightness", self.brightness().await),
+            // the backlight device does not know the display mode
+            ..DisplayState::default()
+        })
+    }
+}
+
+struct Wayland {
+    backlight: Option<Sysfs>,
+}
+
+impl Wayland {
+    /// Returns the output (connector) name and its power state. The detected monitor name is
+    /// usually the model on Wayland, so it is matched against the connector names first, then
+    /// against the output descriptions, reported by wlr-randr. The first output is used if
+    /// nothing matches
+    async fn output(&self) -> EResult<(String, bool)> {
+        // wlopm prints "NAME on|off" lines
+        let outputs: Vec<(String, bool)> = system_cmd_output("wlopm", Vec::<&str>::new())
+            .await?
+            .iter()
+            .filter_map(|line| {
+                let (name, power) = line.split_once(char::is_whitespace)?;
+                Some((name.to_owned(), power.trim() == "on"))
+            })
+            .collect();
+        let find = |connector: &str| outputs.iter().find(|(name, _)| name == connector);
+        let Some(monitor) = crate::MONITOR.get() else {
+            return outputs
+                .first()
+                .cloned()
+                .ok_or_else(|| Error::not_found("no Wayland outputs found"));
+        };
+        if let Some(output) = find(monitor.as_str()) {
+            return Ok(output.clone());
+        }
+        let connector = state_field("outputs", wlr_randr_outputs().await)
+            .unwrap_or_default()
+            .into_iter()
+            .find(|(_, description)| description.contains(monitor.as_str()))
+            .map(|(connector, _)| connector);
+        if let Some(output) = connector.as_deref().and_then(find) {
+            return Ok(output.clone());
+        }
+        let output = outputs
+            .first()
+            .cloned()
+            .ok_or_else(|| Error::not_found("no Wayland outputs found"))?;
+        warn!(
+            "Wayland output for monitor {} not found, using {}",
+            monitor, output.0
+        );
+        Ok(output)
+    }
+    fn backlight(&self) -> EResult<&Sysfs> {
+        self.backlight.as_ref().ok_or_else(|| {
+            Error::unsupported("brightness control on Wayland requires a backlight device")
+        })
+    }
+}
+
+/// Returns (connector, description) of outputs from "wlr-randr" output, output header lines
+/// are not indented and look like: HDMI-A-1 "Dell Inc. DELL U2412M 0FFXD (HDMI-A-1)"
+async fn wlr_randr_outputs() -> EResult<Vec<(String, String)>> {
+    let out = system_cmd_output("wlr-randr", Vec::<&str>::new()).await?;
+    Ok(out
+        .iter()
+        .filter(|line| !line.starts_with(char::is_whitespace))
+        .filter_map(|line| {
+            let (connector, description) = line.split_once(char::is_whitespace)?;
+            Some((
+                connector.to_owned(),
+                description.trim().trim_matches('"').to_owned(),
+            ))
+        })
+        .collect())
+}
+
+/// Returns (power, resolution) of the output from "wlr-randr" output
+async fn wlr_randr_state(output: &str) -> EResult<(bool, Option<(u32, u32)>)> {
+    let out = system_cmd_output("wlr-randr", Vec::<&str>::new()).await?;
+    let mut lines = out
+        .iter()
+        .skip_while(|line| line.split_whitespace().next() != Some(output));
+    if lines.next().is_none() {
+        return Err(Error::not_found(format!("output {} not found", output)));
+    }
+    let mut power = false;
+    let mut resolution = None;
+    // the output properties are indented
+    for line in lines.take_while(|line| line.starts_with(char::is_whitespace)) {
+        let line = line.trim();
+        if let Some(enabled) = line.strip_prefix("Enabled:") {
+            power = enabled.trim() == "yes";
+        } else if line.contains("current") {
+            resolution = line
+                .split_whitespace()
+                .next()
+                .and_then(|mode| parse_resolution(mode, 'x'));
+        }
+    }
+    Ok((power, resolution))
+}
+
+#[async_trait::async_trait]
+impl Backend for Wayland {
+    async fn set_power(&self, on: bool) -> EResult<()> {
+        let (output, _) = self.output().await?;
+        info!("setting display {} on={}", output, on);
+        system_cmd(
+            "wlopm",
+            vec![if on { "--on" } else { "--off" }, output.as_str()],
+        )
+        .await
+    }
+    async fn set_brightness(&self, brightness: f32) -> EResult<()> {
+        self.backlight()?.set_brightness(brightness).await
+    }
+    async fn state(&self) -> EResult<DisplayState> {
+        let (output, power) = self.output().await?;
+        // wlopm reports the DPMS power, wlr-randr reports if the output is enabled
+        let (enabled, resolution) =
+            state_field("resolution", wlr_randr_state(&output).await).unwrap_or((true, None));
+        let brightness = if let Some(ref backlight) = self.backlight {
+            state_field("brightness", backlight.brightness().await)
+        } else {
+            None
+        };
+        Ok(DisplayState {
+            power: Some(power && enabled),
+            brightness,
+            resolution,
+            ..DisplayState::default()
+        })
+    }
+    /// wlroots compositors apply the output transform to touch devices mapped to the output
+    async fn set_rotation(&self, rotation: Rotation) -> EResult<()> {
+        let (output, _) = self.output().await?;
+        info!("setting display {} rotation={}", output, rotation);
+        system_cmd(
+            "wlr-randr",
+            vec![
+                "--output",
+                output.as_str(),
+                "--transform",
+                rotation.wayland_transform(),
+            ],
+        )
+        .await
+    }
+}
+
+fn backlight_device(dir: &str, device: Option<&str>) -> EResult<PathBuf> {
+    if let Some(device) = device {
+        let path = Path::new(dir).join(device);
+        if path.is_dir() {
+            Ok(path)
+        } else {
+            Err(Error::not_found(format!(
+                "backlight device {} not found",
+                path.display()
+            )))
+        }
+    } else {
+        let mut devices: Vec<PathBuf> = std::fs::read_dir(dir)
+            .map_err(|e| Error::io(format!("Unable to read {}: {}", dir, e)))?
+            .filter_map(|entry| entry.ok().map(|e| e.path()))
+            .collect();
+        devices.sort();
+        devices
+            .into_iter()
+            .next()
+            .ok_or_else(|| Error::not_found(format!("no backlight devices in {}", dir)))
+    }
+}
+
+/// Creates a backend and sets it as the active one
+pub fn init(config: &DisplayConfig) -> EResult<()> {
+    set(create(config)?, config.rotation);
+    Ok(())
+}
+
+/// Creates a backend for the config
+pub fn create(config: &DisplayConfig) -> EResult<Arc<dyn Backend>> {
+    let kind = config.backend.resolve();
+    let backend: Arc<dyn Backend> = match kind {
+        BackendKind::Auto | BackendKind::Xrandr => Arc::new(Xrandr {
+            touchscreen: config.touchscreen.clone(),
+        }),
+        BackendKind::Dpms => Arc::new(Dpms {
+            touchscreen: config.touchscreen.clone(),
+        }),
+        BackendKind::Sysfs => {
+            let dir = backlight_device(&config.backlight_dir, config.backlight.as_deref())?;
+            info!("backlight device: {}", dir.display());
+            Arc::new(Sysfs { dir })
+        }
+        BackendKind::Wayland => {
+            let backlight =
+                match backlight_device(&config.backlight_dir, config.backlight.as_deref()) {
+                    Ok(dir) => {
+                        info!("backlight device: {}", dir.display());
+                        Some(Sysfs { dir })
+                    }
+                    Err(e) => {
+                        info!("no backlight device, brightness control disabled: {}", e);
+                        None
+                    }
+                };
+            Arc::new(Wayland { backlight })
+        }
+    };
+    info!("display backend: {}", kind);
+    Ok(backend)
+}
+
+/// Sets the active backend and the configured rotation
+pub fn set(backend: Arc<dyn Backend>, rotation: Option<Rotation>) {
+    *BACKEND.write().unwrap() = Some(backend);
+    *ROTATION.write().unwrap() = rotation;
+}
+
+pub fn backend() -> EResult<Arc<dyn Backend>> {
+    BACKEND
+        .read()
+        .unwrap()
+        .clone()
+        .ok_or_else(|| Error::not_ready("display backend is not available"))
+}
+
+pub async fn set_brightness(brightness: f32) -> EResult<()> {
+    if !(0.0..=100.0).contains(&brightness) {
+        return Err(Error::invalid_params("brightness must be in range 0..100%"));
+    }
+    info!("setting display brightness to {}%", brightness);
+    if commands::is_set(Action::Brightness) {
+        return commands::run(
+            Action::Brightness,
+            &[
+                ("monitor", monitor_var()),
+                ("brightness", brightness.to_string()),
+            ],
+        )
+        .await;
+    }
+    backend()?.set_brightness(brightness).await
+}
+
+pub async fn set_power(on: bool) -> EResult<()> {
+    let action = if on {
+        Action::DisplayOn
+    } else {
+        Action::DisplayOff
+    };
+    if commands::is_set(action) {
+        return commands::run(action, &[("monitor", monitor_var())]).await;
+    }
+    backend()?.set_power(on).await
+}
+
+#[inline]
+fn monitor_var() -> String {
+    crate::MONITOR.get().cloned().unwrap_or_default()
+}
+
+/// Sets brightness and power, used by both the RPC and the schedule
+pub async fn apply(on: Option<bool>, brightness: Option<f32>) -> EResult<()> {
+    if let Some(brightness) = brightness {
+        set_brightness(brightness).await?;
+    }
+    if let Some(on) = on {
+        set_power(on).await?;
+    }
+    Ok(())
+}
+
+#[inline]
+fn rotation() -> Option<Rotation> {
+    *ROTATION.read().unwrap()
+}
+
+pub async fn set_rotation(rotation: Rotation) -> EResult<()> {
+    backend()?.set_rotation(rotation).await?;
+    *ROTATION.write().unwrap() = Some(rotation);
+    Ok(())
+}
+
+/// Applies the configured rotation
+pub async fn apply_rotation() {
+    if let Some(rotation) = rotation() {
+        set_rotation(rotation).await.log_ef();
+    }
+}
+
+pub async fn state() -> EResult<DisplayState> {
+    let mut state = backend()?.state().await?;
+    state.monitor = crate::MONITOR.get().cloned();
+    state.rotation = rotation();
+    Ok(state)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Creates a fake backlight_dir with a single device
+    fn backlight_dir(name: &str, files: &[(&str, &str)]) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("evapanel-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let device = dir.join("panel_backlight");
+        std::fs::create_dir_all(&device).unwrap();
+        for (file, value) in files {
+            std::fs::write(device.join(file), format!("{}\n", value)).unwrap();
+        }
+        dir
+    }
+
+    fn sysfs(dir: &Path) -> Sysfs {
+        Sysfs {
+            dir: backlight_device(&dir.to_string_lossy(), None).unwrap(),
+        }
+    }
+
+    async fn read(sysfs: &Sysfs, name: &str) -> String {
+        tokio::fs::read_to_string(sysfs.dir.join(name))
+            .await
+            .unwrap()
+            .trim()
+            .to_owned()
+    }
+
+    #[tokio::test]
+    async fn sysfs_brightness() {
+        let dir = backlight_dir(
+            "brightness",
+            &[
+                ("max_brightness", "200"),
+                ("brightness", "150"),
+                ("actual_brightness", "50"),
+                ("bl_power", "0"),
+            ],
+        );
+        let sysfs = sysfs(&dir);
+        // the actual brightness is reported
+        assert_eq!(sysfs.state().await.unwrap().brightness(), Some(25.0));
+        sysfs.set_brightness(40.0).await.unwrap();
+        assert_eq!(read(&sysfs, "brightness").await, "80");
+        sysfs.set_brightness(33.3).await.unwrap();
+        assert_eq!(read(&sysfs, "brightness").await, "67");
+        sysfs.set_brightness(100.0).await.unwrap();
+        assert_eq!(read(&sysfs, "brightness").await, "200");
+        std::fs::write(sysfs.dir.join("max_brightness"), "0").unwrap();
+        assert!(sysfs.brightness().await.is_err());
+        assert_eq!(sysfs.state().await.unwrap().brightness(), None);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn sysfs_power() {
+        let dir = backlight_dir(
+            "power",
+            &[
+                ("max_brightness", "100"),
+                ("brightness", "100"),
+                ("actual_brightness", "100"),
+                ("bl_power", "0"),
+            ],
+        );
+        let sysfs = sysfs(&dir);
+        assert_eq!(sysfs.state().await.unwrap().power, Some(true));
+        sysfs.set_power(false).await.unwrap();
+        assert_eq!(read(&sysfs, "bl_power").await, BL_POWER_OFF);
+        assert_eq!(sysfs.state().await.unwrap().power, Some(false));
+        sysfs.set_power(true).await.unwrap();
+        assert_eq!(read(&sysfs, "bl_power").await, BL_POWER_ON);
+        assert_eq!(sysfs.state().await.unwrap().power, Some(true));
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn sysfs_no_power() {
+        let dir = backlight_dir(
+            "no-power",
+            &[
+                ("max_brightness", "10"),
+                ("brightness", "5"),
+                ("actual_brightness", "5"),
+            ],
+        );
+        let state = sysfs(&dir).state().await.unwrap();
+        assert_eq!(state.power, None);
+        assert_eq!(state.brightness(), Some(50.0));
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -1,17 +1,77 @@
-use crate::common::{system_cmd, system_cmd_x, AlertLevel, BusConfig, BusMode, PanelInfo, UEvent};
+use crate::commands::Action;
+use crate::common::{
+    AlertLevel, BusConfig, BusMode, PanelEvent, PanelInfo, ScreenshotOptions, UEvent,
+};
+use crate::display::Rotation;
+use busrt::client::AsyncClient;
 use busrt::rpc::{Rpc, RpcClient, RpcError, RpcEvent, RpcHandlers, RpcResult};
+use busrt::QoS;
 use eva_common::payload::{pack, unpack};
 use eva_common::Error;
 use eva_common::{err_logger, EResult};
-use log::{error, info, warn};
-use serde::Deserialize;
-use std::sync::atomic;
-use std::time::Duration;
+use log::{debug, error, info};
+use once_cell::sync::OnceCell;
+use serde::{Deserialize, Serialize};
+use std::sync::{atomic, Arc};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 use tao::event_loop::EventLoopProxy;
+use tokio::sync::Mutex;
 
 err_logger!();
 
 const DEFAULT_BUS_TIMEOUT: Duration = Duration::from_secs(5);
+const SCREENSHOT_TIMEOUT: Duration = Duration::from_secs(5);
+const DEFAULT_EVAL_TIMEOUT: f64 = 5.0;
+const EVENT_QUEUE_SIZE: usize = 64;
+
+static EVENT_TX: OnceCell<async_channel::Sender<QueuedEvent>> = OnceCell::new();
+
+/// Events are queued while the bus client is disconnected and published after reconnecting,
+/// so the creation time is added to the payload
+#[derive(Serialize)]
+struct QueuedEvent {
+    #[serde(flatten)]
+    event: PanelEvent,
+    /// UNIX timestamp
+    t: f64,
+}
+
+/// Queues an event to be published to the bus, the event is dropped if the bus is not configured
+/// or the queue is full
+pub fn notify(event: PanelEvent) {
+    if let Some(tx) = EVENT_TX.get() {
+        let t = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs_f64())
+            .unwrap_or_default();
+        if tx.try_send(QueuedEvent { event, t }).is_err() {
+            debug!("bus event queue is full, event dropped");
+        }
+    }
+}
+
+async fn publish_events(
+    client: Arc<Mutex<dyn AsyncClient>>,
+    rx: async_channel::Receiver<QueuedEvent>,
+) {
+    while let Ok(event) = rx.recv().await {
+        let payload = match pack(&event) {
+            Ok(v) => v,
+            Err(e) => {
+                error!("unable to pack bus event: {}", e);
+                continue;
+            }
+        };
+        if let Err(e) = client
+            .lock()
+            .await
+            .publish(event.event.topic(), payload.into(), QoS::No)
+            .await
+        {
+            error!("unable to publish bus event: {}", e);
+        }
+    }
+}
 
 #[derive(Clone)]
 pub struct Handlers {
@@ -24,17 +84,6 @@ fn default_alert_timeout() -> u16 {
     30
 }
 
-async fn set_display(monitor: &str, on: bool) -> EResult<()> {
-    let args = vec!["--output", monitor, if on { "--auto" } else { "--off" }];
-    info!("setting display {} on={}", monitor, on);
-    if on {
-        system_cmd("xrandr", args).await
-    } else {
-        // xrandr exit code 1 is ok for off
-        system_cmd_x("xrandr", args, &[1]).await
-    }
-}
-
 #[async_trait::async_trait]
 impl RpcHandlers for Handlers {
     #[allow(clippy::too_many_lines)]
@@ -54,7 +103,9 @@ impl RpcHandlers for Handlers {
             };
         }
         let payload = event.payload();
-        match event.parse_method()? {
+        let method = event.parse_method()?;
+        crate::acl::check(method, event.primary_sender())?;
+        match method {
             "login" => {
                 #[derive(Deserialize)]
                 #[serde(deny_unknown_fields)]
@@ -101,13 +152,49 @@ impl RpcHandlers for Handlers {
                         .await
                         .map_err(|_| Error::timeout())?
                         .map_err(Error::failed)?;
-                    Ok(Some(pack(
-                        &self.info.state_info(state, current_url.as_deref()),
-                    )?))
+                    let display = crate::display::state().await.ok();
+                    Ok(Some(pack(&self.info.state_info(
+                        state,
+                        current_url.as_deref(),
+                        display,
+                    ))?))
+                } else {
+                    Err(RpcError::params(None))
+                }
+            }
+            "monitors" => {
+                if payload.is_empty() {
+                    let (tx, rx) = async_channel::bounded(1);
+                    send_event!(UEvent::GetMonitors(tx));
+                    let monitors = tokio::time::timeout(Duration::from_secs(1), rx.recv())
+                        .await
+                        .map_err(|_| Error::timeout())?
+                        .map_err(Error::failed)?;
+                    Ok(Some(pack(&monitors)?))
                 } else {
                     Err(RpcError::params(None))
                 }
             }
+            "screenshot" => {
+                let opts: ScreenshotOptions = if payload.is_empty() {
+                    ScreenshotOptions::default()
+                } else {
+                    unpack(payload)?
+                };
+                if !(opts.scale > 0.0 && opts.scale <= 1.0) {
+                    return Err(Error::invalid_params("scale must be in range (0, 1]").into());
+                }
+                if opts.quality.is_some_and(|q| !(1..=100).contains(&q)) {
+                    return Err(Error::invalid_params("quality must be in range 1..100").into());
+                }
+                let (tx, rx) = async_channel::bounded(1);
+                send_event!(UEvent::Screenshot(opts, tx));
+                let screenshot = tokio::time::timeout(SCREENSHOT_TIMEOUT, rx.recv())
+                    .await
+                    .map_err(|_| Error::timeout())?
+                    .map_err(Error::failed)??;
+                Ok(Some(pack(&screenshot)?))
+            }
             "alert" => {
                 #[derive(Deserialize)]
                 #[serde(deny_unknown_fields)]
@@ -142,6 +229,32 @@ impl RpcHandlers for Handlers {
                     Ok(None)
                 }
             }
+            "eval.result" => {
+                #[derive(Deserialize)]
+                #[serde(deny_unknown_fields)]
+                struct ParamsEvalResult {
+                    code: String,
+                    timeout: Option<f64>,
+                }
+                if payload.is_empty() {
+                    Err(RpcError::params(None))
+                } else {
+                    let p: ParamsEvalResult = unpack(payload)?;
+                    let timeout = p.timeout.unwrap_or(DEFAULT_EVAL_TIMEOUT);
+                    if timeout <= 0.0 {
+                        return Err(Error::invalid_params("invalid timeout").into());
+                    }
+                    let timeout = Duration::try_from_secs_f64(timeout)
+                        .map_err(|_| Error::invalid_params("invalid timeout"))?;
+                    let (tx, rx) = async_channel::bounded(1);
+                    send_event!(UEvent::EvalWithResult(p.code, tx));
+                    let result = tokio::time::timeout(timeout, rx.recv())
+                        .await
+                        .map_err(|_| Error::timeout())?
+                        .map_err(Error::failed)??;
+                    Ok(Some(pack(&result)?))
+                }
+            }
             "navigate" => {
                 #[derive(Deserialize)]
                 #[serde(deny_unknown_fields)]
@@ -157,6 +270,74 @@ impl RpcHandlers for Handlers {
                     Ok(None)
                 }
             }
+            "urls.list" => {
+                if payload.is_empty() {
+                    Ok(Some(pack(&crate::policy::info())?))
+                } else {
+                    Err(RpcError::params(None))
+                }
+            }
+            method @ ("urls.add" | "urls.remove") => {
+                #[derive(Deserialize)]
+                #[serde(deny_unknown_fields)]
+                struct ParamsUrl {
+                    url: String,
+                    #[serde(default)]
+                    deny: bool,
+                    #[serde(default)]
+                    save: bool,
+                }
+                if payload.is_empty() {
+                    Err(RpcError::params(None))
+                } else {
+                    let p: ParamsUrl = unpack(payload)?;
+                    // earlier unsaved changes are saved as well, even if the call changes nothing
+                    if method == "urls.add" {
+                        info!("adding URL pattern {} (deny: {})", p.url, p.deny);
+                        crate::policy::add(p.url, p.deny)?;
+                    } else {
+                        info!("removing URL pattern {} (deny: {})", p.url, p.deny);
+                        crate::policy::remove(&p.url, p.deny);
+                    }
+                    if p.save {
+                        crate::policy::save(crate::CONFIG_PATH.get().unwrap()).await?;
+                    }
+                    Ok(None)
+                }
+            }
+            "urls.allow_any" => {
+                #[derive(Deserialize)]
+                #[serde(deny_unknown_fields)]
+                struct ParamsAllowAny {
+                    allow: bool,
+                    #[serde(default)]
+                    save: bool,
+                }
+                if payload.is_empty() {
+                    Err(RpcError::params(None))
+                } else {
+                    let p: ParamsAllowAny = unpack(payload)?;
+                    info!("setting allow any URL to {}", p.allow);
+                    crate::policy::set_allow_any(p.allow);
+                    if p.save {
+                        crate::policy::save(crate::CONFIG_PATH.get().unwrap()).await?;
+                    }
+                    Ok(None)
+                }
+            }
+            "config.reload" => {
+                if payload.is_empty() {
+                    let (tx, rx) = async_channel::bounded(1);
+                    send_event!(UEvent::ReloadConfig(Some(tx)));
+                    let report = tokio::time::timeout(Duration::from_secs(1), rx.recv())
+                        .await
+                        .map_err(|_| Error::timeout())?
+                        .map_err(Error::failed)??;
+                    Ok(Some(pack(&report)?))
+                } else {
+                    Err(RpcError::params(None))
+                }
+            }
             "display" => {
                 #[derive(Deserialize)]
                 #[serde(deny_unknown_fields)]
@@ -165,30 +346,28 @@ impl RpcHandlers for Handlers {
                     brightness: Option<f32>,
                 }
                 if payload.is_empty() {
-                    Err(RpcError::params(None))
-                } else if let Some(monitor) = crate::MONITOR.get() {
+                    Ok(Some(pack(&crate::display::state().await?)?))
+                } else {
                     let p: ParamsDisplay = unpack(payload)?;
-                    if let Some(brightness) = p.brightness {
-                        if brightness > 100.0 {
-                            return Err(Error::invalid_params("brightness > 100%").into());
-                        }
-                        info!("setting display brightness to {}%", brightness);
-                        let br_str = brightness.to_string();
-                        let args = vec!["-set", &br_str];
-                        system_cmd("xbacklight", args).await?;
-                    }
-                    if let Some(on) = p.on {
-                        if on {
-                            set_display(monitor, false).await?;
-                            tokio::time::sleep(Duration::from_millis(500)).await;
-                            set_display(monitor, true).await?;
-                        } else {
-                            set_display(monitor, false).await?;
-                        }
+                    crate::display::apply(p.on, p.brightness).await?;
+                    if crate::schedule::is_active() {
+                        info!("display schedule overridden until the next transition");
                     }
                     Ok(None)
+                }
+            }
+            "display.rotate" => {
+                #[derive(Deserialize)]
+                #[serde(deny_unknown_fields)]
+                struct ParamsRotate {
+                    rotation: Rotation,
+                }
+                if payload.is_empty() {
+                    Err(RpcError::params(None))
                 } else {
-                    Err(Error::failed("monitor not detected").into())
+                    let p: ParamsRotate = unpack(payload)?;
+                    crate::display::set_rotation(p.rotation).await?;
+                    Ok(None)
                 }
             }
             "zoom" => {
@@ -201,6 +380,7 @@ impl RpcHandlers for Handlers {
                     Err(RpcError::params(None))
                 } else {
                     let p: ParamsZoom = unpack(payload)?;
+                    crate::config::check_zoom(p.level)?;
                     send_event!(UEvent::Zoom(p.level));
                     Ok(None)
                 }
@@ -213,14 +393,63 @@ impl RpcHandlers for Handlers {
                     Err(RpcError::params(None))
                 }
             }
-            "reboot" => {
+            "volume" => {
+                #[derive(Deserialize)]
+                #[serde(deny_unknown_fields)]
+                struct ParamsVolume {
+                    level: f32,
+                }
+                if payload.is_empty() {
+                    Err(RpcError::params(None))
+                } else {
+                    let p: ParamsVolume = unpack(payload)?;
+                    if !(0.0..=100.0).contains(&p.level) {
+                        return Err(Error::invalid_params("volume must be in range 0..100%").into());
+                    }
+                    info!("setting volume to {}%", p.level);
+                    crate::commands::run(Action::Volume, &[("volume", p.level.to_string())])
+                        .await?;
+                    Ok(None)
+                }
+            }
+            method @ ("reboot" | "poweroff") => {
+                #[derive(Deserialize)]
+                #[serde(deny_unknown_fields)]
+                struct ParamsSystemAction {
+                    delay: Option<f64>,
+                }
+                let delay = if payload.is_empty() {
+                    None
+                } else {
+                    let p: ParamsSystemAction = unpack(payload)?;
+                    p.delay
+                        .map(Duration::try_from_secs_f64)
+                        .transpose()
+                        .map_err(|_| Error::invalid_params("invalid delay"))?
+                };
+                let action = if method == "reboot" {
+                    Action::Reboot
+                } else {
+                    Action::Poweroff
+                };
+                crate::commands::schedule(action, delay, self.api_proxy.clone())?;
+                Ok(None)
+            }
+            "session.restart" => {
+                if payload.is_empty() {
+                    crate::commands::schedule(
+                        Action::SessionRestart,
+                        None,
+                        self.api_proxy.clone(),
+                    )?;
+                    Ok(None)
+                } else {
+                    Err(RpcError::params(None))
+                }
+            }
+            "reboot.cancel" => {
                 if payload.is_empty() {
-                    let args = vec!["-c", crate::REBOOT_CMD.get().unwrap()];
-                    warn!("calling reboot command");
-                    tokio::spawn(async move {
-                        tokio::time::sleep(Duration::from_secs(2)).await;
-                        system_cmd("sh", args).await.log_ef();
-                    });
+                    crate::commands::cancel(&self.api_proxy)?;
                     Ok(None)
                 } else {
                     Err(RpcError::params(None))
@@ -261,9 +490,20 @@ pub async fn launch_bus(
         api_proxy,
         info: panel_info,
     };
+    let (event_tx, event_rx) = async_channel::bounded(EVENT_QUEUE_SIZE);
+    EVENT_TX.set(event_tx).unwrap();
     match bus.mode() {
         #[cfg(target_os = "linux")]
         BusMode::Server => {
+            if bus.is_unix_sock()
+                && std::path::Path::new(path).exists()
+                && tokio::net::UnixStream::connect(path).await.is_ok()
+            {
+                return Err(Error::busy(format!(
+                    "socket {} is used by another instance",
+                    path
+                )));
+            }
             let mut broker = busrt::broker::Broker::new();
             let server_config = busrt::broker::ServerConfig::new().timeout(DEFAULT_BUS_TIMEOUT);
             if bus.is_unix_sock() {
@@ -273,15 +513,22 @@ pub async fn launch_bus(
                 broker.spawn_tcp_server(path, server_config).await?;
                 info!("BUS/RT control TCP socket: {}", path);
             }
-            let client = broker.register_client(".panel").await.unwrap();
-            let _rpc = RpcClient::new(client, handlers);
+            let client = broker.register_client(bus.name()).await?;
+            let rpc = RpcClient::new(client, handlers);
+            tokio::spawn(publish_events(rpc.client(), event_rx));
             while crate::is_active() {
                 tokio::time::sleep(Duration::from_millis(200)).await;
             }
             Ok(())
         }
+        #[cfg(not(target_os = "linux"))]
+        BusMode::Server => Err(Error::unsupported(
+            "bus server mode is supported on Linux only",
+        )),
         BusMode::Client => loop {
-            if let Err(e) = handle_bus_client(path, handlers.clone()).await {
+            if let Err(e) =
+                handle_bus_client(path, bus.name(), handlers.clone(), event_rx.clone()).await
+            {
                 error!("BUS/RT client error: {}", e);
             }
             tokio::time::sleep(Duration::from_secs(5)).await;
@@ -289,20 +536,23 @@ pub async fn launch_bus(
     }
 }
 
-async fn handle_bus_client(path: &str, handlers: Handlers) -> EResult<()> {
-    let name = format!(
-        "eva.panel.{}",
-        hostname::get().map_err(Error::failed)?.to_string_lossy()
-    );
+async fn handle_bus_client(
+    path: &str,
+    name: &str,
+    handlers: Handlers,
+    event_rx: async_channel::Receiver<QueuedEvent>,
+) -> EResult<()> {
     let client = busrt::ipc::Client::connect(
-        &busrt::ipc::Config::new(path, &name).timeout(DEFAULT_BUS_TIMEOUT),
+        &busrt::ipc::Config::new(path, name).timeout(DEFAULT_BUS_TIMEOUT),
     )
     .await?;
     info!("connected to BUS/RT broker at {} as {}", path, name);
     let rpc = RpcClient::new(client, handlers);
+    let publisher = tokio::spawn(publish_events(rpc.client(), event_rx));
     while rpc.client().lock().await.is_connected() {
         tokio::time::sleep(Duration::from_millis(200)).await;
     }
+    publisher.abort();
     Ok(())
 }
 
@@ -312,6 +562,7 @@ pub fn launch(bus: &BusConfig, api_proxy: EventLoopProxy<UEvent>, panel_info: Pa
         .build()
         .unwrap();
     rt.block_on(async {
+        crate::spawn_tasks(api_proxy.clone());
         if launch_bus(bus, api_proxy, panel_info)
             .await
             .log_err()
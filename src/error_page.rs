@@ -0,0 +1,27 @@
+use crate::common::{PageStatus, PanelEvent};
+use log::warn;
+use webkit2gtk::{NetworkError, PolicyError, WebViewExt};
+use wry::WebViewExtUnix;
+
+/// Replaces the engine error page with the bundled one
+pub fn attach(webview: &wry::WebView) {
+    webview
+        .webview()
+        .connect_load_failed(|wv, _event, failing_uri, error| {
+            // cancelled and blocked loads are not errors
+            if error.matches(NetworkError::Cancelled) || error.kind::<PolicyError>().is_some() {
+                return false;
+            }
+            warn!("page {} load failed: {}", failing_uri, error);
+            crate::eapi::notify(PanelEvent::Page {
+                status: PageStatus::Failed,
+                url: failing_uri.to_owned(),
+            });
+            wv.load_alternate_html(
+                &crate::protocol::error_page(failing_uri, error.message()),
+                failing_uri,
+                None,
+            );
+            true
+        });
+}
@@ -1,28 +1,363 @@
-use crate::common::{prepare_js_str, BusConfig, State, UEvent};
-use eva_common::err_logger;
-use log::{error, info};
+use crate::bridge::Bridge;
+use crate::common::{
+    prepare_js_str, MonitorInfo, PanelEvent, Screenshot, ScreenshotOptions, State, UEvent,
+};
+use crate::config::{Config, ReloadReport};
+use crate::hang::{Step, Watchdog};
+use crate::idle::{AutoLogout, IdleState};
+use crate::policy::Policy;
+use eva_common::{err_logger, EResult, Error};
+use log::{error, info, warn};
+use serde::Deserialize;
+use std::sync::atomic;
+use std::time::{Duration, Instant};
 use tao::{
     event::{ElementState, Event, StartCause, WindowEvent},
     event_loop::{ControlFlow, EventLoop},
     keyboard::KeyCode,
+    window::Window,
 };
 use wry::WebView;
 
 err_logger!();
 
+const STATE_CHECK_INTERVAL: Duration = Duration::from_millis(500);
+
+const STATE_JS: &str = r"{
+    let result = 0;
+    if (window.$eva.api_token) {
+        result = 2;
+    } else if (window.$eva.hmi.login) {
+        result = 1;
+    }
+    result
+    }";
+
+#[derive(Deserialize)]
+struct EvalResult {
+    ok: bool,
+    #[serde(default)]
+    result: serde_json::Value,
+    #[serde(default)]
+    error: Option<String>,
+}
+
+fn eval_with_result(
+    webview: &WebView,
+    code: &str,
+    resp: async_channel::Sender<EResult<serde_json::Value>>,
+) {
+    // the code is passed as a JSON string literal and evaluated in the global scope, exceptions
+    // are caught and returned to the caller
+    let script = format!(
+        r"(function() {{
+            try {{
+                return {{ ok: true, result: (0, eval)({}) }};
+            }} catch (e) {{
+                return {{ ok: false, error: String(e) }};
+            }}
+        }})()",
+        serde_json::Value::String(code.to_owned())
+    );
+    webview
+        .evaluate_script_with_callback(&script, move |res| {
+            let result = match serde_json::from_str::<EvalResult>(&res) {
+                Ok(v) if v.ok => Ok(v.result),
+                Ok(v) => Err(Error::failed(format!(
+                    "script exception: {}",
+                    v.error.unwrap_or_default()
+                ))),
+                Err(_) => Err(Error::failed("unable to get the script result")),
+            };
+            let _r = resp.send_blocking(result);
+        })
+        .log_ef();
+}
+
+fn navigate(webview: &WebView, url: &str) {
+    webview
+        .evaluate_script(&format!(r#"document.location = "{}""#, prepare_js_str(url)))
+        .log_ef();
+}
+
+#[allow(clippy::float_cmp)]
+fn reload_config(window: &Window, webview: &WebView, config: &mut Config) -> EResult<ReloadReport> {
+    let (mut new_config, _) = Config::load(crate::CONFIG_PATH.get().unwrap())?;
+    if let Some((key, problem)) = new_config.validate().into_iter().next() {
+        return Err(Error::invalid_data(format!("{}: {}", key, problem)));
+    }
+    if let Some(ref mut bus) = new_config.bus {
+        bus.resolve(new_config.monitor.is_some())?;
+    }
+    let home_url_changed = new_config.home_url != config.home_url;
+    let urls_changed = new_config.allowed_urls != config.allowed_urls
+        || new_config.denied_urls != config.denied_urls;
+    // fallible objects are created before anything is applied, so a failed reload changes
+    // nothing
+    let policy = if home_url_changed || urls_changed {
+        Some(Policy::new(
+            &new_config.home_url,
+            &new_config.allowed_urls,
+            &new_config.denied_urls,
+        )?)
+    } else {
+        None
+    };
+    let display = if new_config.display == config.display {
+        None
+    } else {
+        Some(crate::display::create(&new_config.display)?)
+    };
+    let auto_logout = if new_config.auto_logout == config.auto_logout {
+        None
+    } else {
+        Some(
+            new_config
+                .auto_logout
+                .as_ref()
+                .map(AutoLogout::new)
+                .transpose()?,
+        )
+    };
+    let bridge = if new_config.bridge == config.bridge {
+        None
+    } else {
+        Some(new_config.bridge.as_ref().map(Bridge::new).transpose()?)
+    };
+    let mut report = ReloadReport::default();
+    if new_config.title != config.title {
+        window.set_title(&new_config.title);
+        report.applied.push("title");
+    }
+    if new_config.zoom != config.zoom {
+        if let Err(e) = webview.zoom(new_config.zoom) {
+            error!("zoom error: {}", e);
+            // retried on the next reload
+            new_config.zoom = config.zoom;
+        } else {
+            report.applied.push("zoom");
+        }
+    }
+    if new_config.show_cursor != config.show_cursor {
+        window.set_cursor_visible(new_config.show_cursor);
+        webview
+            .evaluate_script(&crate::cursor_js(new_config.show_cursor))
+            .log_ef();
+        report.applied.push("show_cursor");
+    }
+    if new_config.fullscreen != config.fullscreen || new_config.window_size != config.window_size {
+        crate::set_window_mode(
+            window,
+            new_config.fullscreen,
+            new_config.window_size,
+            crate::find_monitor(window, config.monitor.as_ref()),
+        );
+        report.applied.push("fullscreen/window_size");
+    }
+    if let Some(backend) = display {
+        crate::display::set(backend, new_config.display.rotation());
+        if new_config.display.rotation() != config.display.rotation() {
+            crate::spawn(crate::display::apply_rotation());
+        }
+        report.applied.push("display");
+    }
+    if new_config.display_schedule != config.display_schedule {
+        crate::schedule::set(new_config.display_schedule.clone());
+        report.applied.push("display_schedule");
+    }
+    if new_config.idle != config.idle {
+        crate::idle::set(new_config.idle.clone());
+        report.applied.push("idle");
+    }
+    if let Some(auto_logout) = auto_logout {
+        crate::idle::set_auto_logout(auto_logout);
+        report.applied.push("auto_logout");
+    }
+    if new_config.connectivity != config.connectivity {
+        crate::connectivity::set(new_config.connectivity.clone());
+        report.applied.push("connectivity");
+    }
+    if new_config.hang_watchdog != config.hang_watchdog {
+        crate::hang::set(new_config.hang_watchdog.clone());
+        report.applied.push("hang_watchdog");
+    }
+    if let Some(bridge) = bridge {
+        crate::bridge::set(bridge);
+        report.applied.push("bridge");
+    }
+    if new_config.acl != config.acl {
+        crate::acl::set(new_config.acl.clone());
+        report.applied.push("acl");
+    }
+    if new_config.commands != config.commands {
+        crate::commands::set(new_config.commands.clone());
+        report.applied.push("commands");
+    }
+    if new_config.pages_dir != config.pages_dir {
+        crate::protocol::set_pages_dir(new_config.pages_dir.as_deref());
+        report.applied.push("pages_dir");
+    }
+    if let Some(policy) = policy {
+        crate::policy::set(policy);
+        if urls_changed {
+            report.applied.push("allowed_urls/denied_urls");
+        }
+    }
+    if home_url_changed {
+        *crate::HOME_URL.write().unwrap() = new_config.home_url.clone();
+        navigate(webview, &new_config.home_url);
+        report.applied.push("home_url");
+    }
+    if new_config.engine != config.engine {
+        report.restart_required.push("engine");
+    }
+    if new_config.debug != config.debug {
+        report.restart_required.push("debug");
+    }
+    if new_config.sig != config.sig {
+        report.restart_required.push("sig");
+    }
+    if new_config.bus != config.bus {
+        report.restart_required.push("bus");
+    }
+    if new_config.monitor != config.monitor {
+        report.restart_required.push("monitor");
+    }
+    // settings, which require restart, keep their running values
+    let running = std::mem::replace(config, new_config);
+    config.engine = running.engine;
+    config.debug = running.debug;
+    config.sig = running.sig;
+    config.bus = running.bus;
+    config.monitor = running.monitor;
+    Ok(report)
+}
+
+static LAST_STATE: atomic::AtomicU8 = atomic::AtomicU8::new(State::Unknown as u8);
+
+fn check_state(webview: &WebView, last_url: &mut Option<String>) {
+    if let Ok(url) = webview.url() {
+        if last_url.as_ref() != Some(&url) {
+            *last_url = Some(url.clone());
+            crate::eapi::notify(PanelEvent::Location { url });
+        }
+    }
+    webview
+        .evaluate_script_with_callback(STATE_JS, |res| {
+            let state: State =
+                serde_json::from_str::<u8>(&res).map_or(State::Preparing, Into::into);
+            let prev = LAST_STATE.swap(state as u8, atomic::Ordering::Relaxed);
+            if prev != state as u8 {
+                crate::eapi::notify(PanelEvent::State { state });
+            }
+        })
+        .log_ef();
+}
+
+#[cfg(target_os = "linux")]
+fn screenshot(
+    webview: &WebView,
+    opts: ScreenshotOptions,
+    resp: async_channel::Sender<EResult<Screenshot>>,
+) {
+    use webkit2gtk::{SnapshotOptions, SnapshotRegion, WebViewExt};
+    use wry::WebViewExtUnix;
+    let region = if opts.full {
+        SnapshotRegion::FullDocument
+    } else {
+        SnapshotRegion::Visible
+    };
+    webview.webview().snapshot(
+        region,
+        SnapshotOptions::NONE,
+        None::<&webkit2gtk::gio::Cancellable>,
+        move |res| {
+            let result = res
+                .map_err(Error::failed)
+                .and_then(|surface| encode(surface, opts.scale, opts.quality));
+            let _r = resp.send_blocking(result);
+        },
+    );
+}
+
+#[cfg(not(target_os = "linux"))]
+fn screenshot(
+    _webview: &WebView,
+    _opts: ScreenshotOptions,
+    resp: async_channel::Sender<EResult<Screenshot>>,
+) {
+    let _r = resp.send_blocking(Err(Error::unsupported(
+        "screenshots are supported on Linux only",
+    )));
+}
+
+#[cfg(target_os = "linux")]
+#[allow(clippy::cast_possible_truncation)]
+fn encode(surface: cairo::Surface, scale: f64, quality: Option<u8>) -> EResult<Screenshot> {
+    let image = cairo::ImageSurface::try_from(surface)
+        .map_err(|_| Error::failed("unsupported snapshot surface"))?;
+    let width = ((f64::from(image.width()) * scale).round() as i32).max(1);
+    let height = ((f64::from(image.height()) * scale).round() as i32).max(1);
+    let target =
+        cairo::ImageSurface::create(cairo::Format::ARgb32, width, height).map_err(Error::failed)?;
+    {
+        let ctx = cairo::Context::new(&target).map_err(Error::failed)?;
+        ctx.scale(scale, scale);
+        ctx.set_source_surface(&image, 0.0, 0.0)
+            .map_err(Error::failed)?;
+        ctx.paint().map_err(Error::failed)?;
+    }
+    if let Some(quality) = quality {
+        let data = gdk::pixbuf_get_from_surface(&target, 0, 0, width, height)
+            .ok_or_else(|| Error::failed("unable to convert the snapshot"))?
+            .save_to_bufferv("jpeg", &[("quality", &quality.to_string())])
+            .map_err(Error::failed)?;
+        return Ok(Screenshot {
+            format: "jpeg",
+            width,
+            height,
+            data,
+        });
+    }
+    let mut data = Vec::new();
+    target.write_to_png(&mut data).map_err(Error::failed)?;
+    Ok(Screenshot {
+        format: "png",
+        width,
+        height,
+        data,
+    })
+}
+
 #[allow(clippy::too_many_lines)]
 #[allow(clippy::cast_possible_truncation)]
 #[allow(clippy::cast_sign_loss)]
 #[allow(deprecated)]
 pub fn run(
     event_loop: EventLoop<UEvent>,
-    webview: WebView,
-    debug: bool,
-    bus_config: Option<BusConfig>,
+    window: Window,
+    mut webview: WebView,
+    mut config: Config,
+    user_agent: String,
 ) {
+    // state changes are watched only if there is a bus to publish them to
+    let watch_state = config.bus.is_some();
+    let mut last_url = None;
+    let mut watchdog = Watchdog::default();
+    let mut next_state_check = Instant::now() + STATE_CHECK_INTERVAL;
+    let proxy = event_loop.create_proxy();
     event_loop.run(move |event, _, control_flow| {
-        *control_flow = ControlFlow::Wait;
+        *control_flow = if watch_state {
+            ControlFlow::WaitUntil(next_state_check)
+        } else {
+            ControlFlow::Wait
+        };
         match event {
+            Event::NewEvents(StartCause::ResumeTimeReached { .. }) => {
+                check_state(&webview, &mut last_url);
+                next_state_check = Instant::now() + STATE_CHECK_INTERVAL;
+                *control_flow = ControlFlow::WaitUntil(next_state_check);
+            }
             Event::UserEvent(ev) => match ev {
                 UEvent::GetLocation(resp) => {
                     webview
@@ -35,26 +370,28 @@ pub fn run(
                         })
                         .log_ef();
                 }
+                UEvent::GetMonitors(resp) => {
+                    let current = window.current_monitor();
+                    let monitors = window
+                        .available_monitors()
+                        .enumerate()
+                        .map(|(index, m)| MonitorInfo::new(index, &m, current.as_ref() == Some(&m)))
+                        .collect();
+                    let _r = resp.send_blocking(monitors);
+                }
+                UEvent::Screenshot(opts, resp) => {
+                    info!("screenshot requested");
+                    screenshot(&webview, opts, resp);
+                }
                 UEvent::GetState(resp) => {
                     webview
-                        .evaluate_script_with_callback(
-                            r"{
-                        let result = 0;
-                        if (window.$eva.api_token) {
-                            result = 2;
-                        } else if (window.$eva.hmi.login) {
-                            result = 1;
-                        }
-                        result
-                        }",
-                            move |res| {
-                                let mut state = State::Preparing;
-                                if let Ok(val) = serde_json::from_str::<u8>(&res) {
-                                    state = val.into();
-                                }
-                                let _r = resp.send_blocking(state);
-                            },
-                        )
+                        .evaluate_script_with_callback(STATE_JS, move |res| {
+                            let mut state = State::Preparing;
+                            if let Ok(val) = serde_json::from_str::<u8>(&res) {
+                                state = val.into();
+                            }
+                            let _r = resp.send_blocking(state);
+                        })
                         .log_ef();
                 }
                 UEvent::Login(login, password) => {
@@ -87,6 +424,10 @@ pub fn run(
                     info!("eval requested");
                     webview.evaluate_script(&script).log_ef();
                 }
+                UEvent::EvalWithResult(code, resp) => {
+                    info!("eval with result requested");
+                    eval_with_result(&webview, &code, resp);
+                }
                 UEvent::Reload => {
                     info!("reload requested");
                     crate::set_stopped();
@@ -99,16 +440,28 @@ pub fn run(
                     }
                 }
                 UEvent::Navigate(n_url) => {
-                    let url = n_url
-                        .as_ref()
-                        .unwrap_or_else(|| crate::HOME_URL.get().unwrap());
+                    let url = n_url.unwrap_or_else(crate::home_url);
                     info!("navigate to {} requested", url);
-                    webview
-                        .evaluate_script(&format!(
-                            r#"document.location = "{}""#,
-                            prepare_js_str(url)
-                        ))
-                        .log_ef();
+                    navigate(&webview, &url);
+                }
+                UEvent::ReloadConfig(resp) => {
+                    info!("config reload requested");
+                    let result = reload_config(&window, &webview, &mut config);
+                    match result {
+                        Ok(ref report) => {
+                            info!("config reloaded, applied: {}", report.applied.join(", "));
+                            if !report.restart_required.is_empty() {
+                                warn!(
+                                    "config settings changed, restart required: {}",
+                                    report.restart_required.join(", ")
+                                );
+                            }
+                        }
+                        Err(ref e) => error!("config reload error: {}", e),
+                    }
+                    if let Some(tx) = resp {
+                        let _r = tx.send_blocking(result);
+                    }
                 }
                 UEvent::OpenDevTools => {
                     webview.open_devtools();
@@ -116,6 +469,52 @@ pub fn run(
                 UEvent::CloseDevTools => {
                     webview.close_devtools();
                 }
+                UEvent::Sleep => {
+                    webview.evaluate_script(crate::idle::SLEEP_JS).log_ef();
+                }
+                UEvent::LogoutWarning(seconds) => {
+                    webview
+                        .evaluate_script(&crate::idle::logout_warning_js(seconds))
+                        .log_ef();
+                }
+                UEvent::Offline(offline) => {
+                    webview
+                        .evaluate_script(&crate::connectivity::offline_js(offline))
+                        .log_ef();
+                }
+                UEvent::ReloadPage => {
+                    info!("reloading the page");
+                    webview.reload().log_ef();
+                }
+                UEvent::BridgeReply(id, result) => {
+                    webview
+                        .evaluate_script(&crate::bridge::reply_js(id, result))
+                        .log_ef();
+                }
+                UEvent::PageLoaded => {
+                    webview
+                        .evaluate_script(&crate::cursor_js(config.show_cursor))
+                        .log_ef();
+                    // the sleeping flag is lost if a page is loaded while the screen is blank
+                    if crate::idle::info().state() == IdleState::Blank {
+                        webview.evaluate_script(crate::idle::SLEEP_JS).log_ef();
+                    }
+                }
+                UEvent::Heartbeat(None) => watchdog.reset(),
+                UEvent::Heartbeat(Some(timeout)) => match watchdog.tick(&webview, timeout) {
+                    Some(Step::Reload) => webview.reload().log_ef(),
+                    Some(Step::Recreate) => {
+                        match crate::create_webview(&window, &config, &user_agent, proxy.clone()) {
+                            Ok(v) => webview = v,
+                            Err(e) => error!("unable to recreate the Web view: {}", e),
+                        }
+                    }
+                    Some(Step::Exit) => {
+                        crate::set_stopped();
+                        *control_flow = ControlFlow::ExitWithCode(crate::hang::EXIT_CODE);
+                    }
+                    None => {}
+                },
             },
             Event::NewEvents(StartCause::Init) => info!("ready"),
             Event::WindowEvent {
@@ -124,18 +523,27 @@ pub fn run(
             } => {
                 info!("window closed, exiting");
                 crate::set_stopped();
-                if let Some(ref bus) = bus_config {
+                if let Some(ref bus) = config.bus {
                     if bus.is_unix_sock() {
                         let _ = std::fs::remove_file(bus.path());
                     }
                 }
                 *control_flow = ControlFlow::Exit;
             }
+            Event::WindowEvent {
+                event:
+                    WindowEvent::Touch(_)
+                    | WindowEvent::MouseInput { .. }
+                    | WindowEvent::MouseWheel { .. }
+                    | WindowEvent::CursorMoved { .. },
+                ..
+            } => crate::idle::activity(),
             Event::WindowEvent {
                 event: WindowEvent::KeyboardInput { event, .. },
                 ..
             } => {
-                if debug && event.state == ElementState::Released {
+                crate::idle::activity();
+                if config.debug && event.state == ElementState::Released {
                     #[allow(clippy::single_match)]
                     match event.physical_key {
                         KeyCode::F12 => {
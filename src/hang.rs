@@ -0,0 +1,148 @@
+use crate::common::{PanelEvent, UEvent};
+use eva_common::err_logger;
+use log::{error, info};
+use serde::{Deserialize, Serialize};
+use std::sync::{atomic, RwLock};
+use std::time::{Duration, Instant};
+use tao::event_loop::EventLoopProxy;
+use wry::WebView;
+
+err_logger!();
+
+/// the check interval if the watchdog is disabled
+const IDLE_INTERVAL: Duration = Duration::from_secs(1);
+/// the process exit code if the renderer does not recover, the launch script restarts the panel
+pub const EXIT_CODE: i32 = 3;
+
+static CONFIG: RwLock<Option<HangWatchdogConfig>> = RwLock::new(None);
+/// the last heartbeat answered by the page
+static REPLY: atomic::AtomicU64 = atomic::AtomicU64::new(0);
+
+#[inline]
+fn default_interval() -> f64 {
+    5.0
+}
+
+#[inline]
+fn default_timeout() -> f64 {
+    10.0
+}
+
+/// Times are in seconds
+#[derive(Deserialize, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct HangWatchdogConfig {
+    #[serde(default = "default_interval")]
+    interval: f64,
+    /// the renderer is considered hung if a heartbeat is not answered within
+    #[serde(default = "default_timeout")]
+    timeout: f64,
+}
+
+impl HangWatchdogConfig {
+    /// Returns a list of (key, problem)
+    pub fn validate(&self) -> Vec<(&'static str, String)> {
+        let mut problems = Vec::new();
+        if self.interval <= 0.0 || !self.interval.is_finite() {
+            problems.push(("hang_watchdog.interval", "must be positive".to_owned()));
+        }
+        if self.timeout <= 0.0 || !self.timeout.is_finite() {
+            problems.push(("hang_watchdog.timeout", "must be positive".to_owned()));
+        }
+        problems
+    }
+}
+
+pub fn set(config: Option<HangWatchdogConfig>) {
+    *CONFIG.write().unwrap() = config;
+}
+
+/// Recovery steps, each next one is taken if the previous has not helped
+#[derive(bmart::tools::EnumStr, Serialize, Copy, Clone, Eq, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Step {
+    /// reloads the page
+    Reload,
+    /// recreates the Web view
+    Recreate,
+    /// exits the process
+    Exit,
+}
+
+/// The heartbeat state, owned by the event loop
+#[derive(Default)]
+pub struct Watchdog {
+    seq: u64,
+    sent: Option<Instant>,
+    step: Option<Step>,
+}
+
+impl Watchdog {
+    /// Called when the watchdog is disabled
+    pub fn reset(&mut self) {
+        self.sent = None;
+        self.step = None;
+    }
+    /// Checks the previous heartbeat and sends a new one, returns a recovery step to take
+    pub fn tick(&mut self, webview: &WebView, timeout: Duration) -> Option<Step> {
+        if let Some(sent) = self.sent {
+            if REPLY.load(atomic::Ordering::Relaxed) >= self.seq {
+                if let Some(step) = self.step.take() {
+                    info!("the renderer has recovered after {}", step);
+                }
+            } else if sent.elapsed() < timeout {
+                return None;
+            } else {
+                let step = match self.step {
+                    None => Step::Reload,
+                    Some(Step::Reload) => Step::Recreate,
+                    Some(Step::Recreate | Step::Exit) => Step::Exit,
+                };
+                error!(
+                    "the renderer has not answered for {:.1} sec, recovery: {}",
+                    sent.elapsed().as_secs_f64(),
+                    step
+                );
+                crate::eapi::notify(PanelEvent::Hang { step });
+                self.step = Some(step);
+                self.sent = None;
+                return Some(step);
+            }
+        }
+        self.seq += 1;
+        let seq = self.seq;
+        self.sent = Some(Instant::now());
+        webview
+            .evaluate_script_with_callback("1", move |_| {
+                REPLY.fetch_max(seq, atomic::Ordering::Relaxed);
+            })
+            .log_ef();
+        None
+    }
+}
+
+/// Asks the event loop to send heartbeats
+pub async fn run(proxy: EventLoopProxy<UEvent>) {
+    let mut enabled = false;
+    loop {
+        let config = CONFIG.read().unwrap().clone();
+        let Some(config) = config else {
+            if enabled {
+                info!("hang watchdog disabled");
+                if proxy.send_event(UEvent::Heartbeat(None)).is_err() {
+                    break;
+                }
+                enabled = false;
+            }
+            tokio::time::sleep(IDLE_INTERVAL).await;
+            continue;
+        };
+        enabled = true;
+        let timeout = Duration::try_from_secs_f64(config.timeout).unwrap_or_default();
+        if proxy.send_event(UEvent::Heartbeat(Some(timeout))).is_err() {
+            break;
+        }
+        tokio::time::sleep(Duration::try_from_secs_f64(config.interval).unwrap_or(IDLE_INTERVAL))
+            .await;
+    }
+}
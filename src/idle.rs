@@ -0,0 +1,405 @@
+use crate::common::{PanelEvent, State, UEvent};
+use crate::policy::UrlList;
+use eva_common::{err_logger, EResult};
+use log::info;
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::sync::{atomic, Arc, Mutex, RwLock};
+use std::time::{Duration, Instant};
+use tao::event_loop::EventLoopProxy;
+
+err_logger!();
+
+const CHECK_INTERVAL: Duration = Duration::from_millis(500);
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(1);
+/// brightness restored on wake up if the brightness before dimming is unknown
+const DEFAULT_BRIGHTNESS: f32 = 100.0;
+
+/// Marks the page as sleeping, the first input after blanking only wakes the screen up
+pub const SLEEP_JS: &str = "window.__evapanel_sleep && window.__evapanel_sleep()";
+
+/// Reports user activity to the native side and swallows the first input on a blank screen
+pub const ACTIVITY_JS: &str = r"(() => {
+    let sleeping = false;
+    let last_sent = 0;
+    let pressed = false;
+    let wake_timer = null;
+    window.__evapanel_sleep = () => {
+      clearTimeout(wake_timer);
+      sleeping = true;
+    };
+    let warning = null;
+    window.__evapanel_logout_warning = (seconds) => {
+      if (warning) {
+        clearInterval(warning.timer);
+        warning.el.remove();
+        warning = null;
+      }
+      if (seconds === null) return;
+      const el = document.createElement('div');
+      el.style.cssText = 'position: fixed; inset: 0; z-index: 2147483647; display: flex; ' +
+        'align-items: center; justify-content: center; text-align: center; ' +
+        'background: rgba(0, 0, 0, 0.7); color: #fff; font: bold 5vmin sans-serif';
+      let left = seconds;
+      const show = () => {
+        el.textContent = `Logging out in ${left} s. Touch the screen to stay logged in`;
+      };
+      show();
+      const timer = setInterval(() => { if (left > 0) { left -= 1; show(); } }, 1000);
+      el.addEventListener('click', () => window.__evapanel_logout_warning(null));
+      document.body.appendChild(el);
+      warning = { el, timer };
+    };
+    const press = ['pointerdown', 'touchstart', 'mousedown', 'keydown'];
+    const release = ['pointerup', 'pointercancel', 'touchend', 'touchcancel', 'mouseup', 'keyup'];
+    const moves = ['pointermove', 'touchmove', 'mousemove', 'wheel'];
+    // the input is swallowed until 500 ms after the last wake up report, a press, which has woken
+    // the screen up, is swallowed until released
+    const wake = () => {
+      clearTimeout(wake_timer);
+      wake_timer = setTimeout(() => {
+        if (!pressed) sleeping = false;
+      }, 500);
+    };
+    const handler = (e) => {
+      if (press.includes(e.type)) {
+        pressed = true;
+      } else if (release.includes(e.type)) {
+        pressed = false;
+      }
+      const now = Date.now();
+      if (sleeping || now - last_sent > 1000) {
+        last_sent = now;
+        window.ipc.postMessage('activity');
+      }
+      if (sleeping) {
+        wake();
+        if (!moves.includes(e.type)) {
+          e.preventDefault();
+          e.stopImmediatePropagation();
+        }
+      }
+    };
+    for (const t of ['click', 'dblclick', 'contextmenu'].concat(press, release, moves)) {
+      window.addEventListener(t, handler, { capture: true, passive: false });
+    }
+  })();";
+
+/// The IPC message, sent by ACTIVITY_JS
+pub const ACTIVITY_MESSAGE: &str = "activity";
+
+static CONFIG: RwLock<Option<IdleConfig>> = RwLock::new(None);
+static LAST_ACTIVITY: Mutex<Option<Instant>> = Mutex::new(None);
+static STATE: atomic::AtomicU8 = atomic::AtomicU8::new(IdleState::Active as u8);
+static AUTO_LOGOUT: RwLock<Option<Arc<AutoLogout>>> = RwLock::new(None);
+
+#[inline]
+fn default_dim_brightness() -> f32 {
+    10.0
+}
+
+/// Timeouts are in seconds since the last user activity
+#[derive(Deserialize, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct IdleConfig {
+    #[serde(default)]
+    dim_after: Option<f64>,
+    #[serde(default = "default_dim_brightness")]
+    dim_brightness: f32,
+    #[serde(default)]
+    blank_after: Option<f64>,
+}
+
+impl IdleConfig {
+    /// Returns a list of (key, problem)
+    pub fn validate(&self) -> Vec<(&'static str, String)> {
+        let mut problems = Vec::new();
+        if self.dim_after.is_some_and(|v| v <= 0.0) {
+            problems.push(("idle.dim_after", "must be positive".to_owned()));
+        }
+        if self.blank_after.is_some_and(|v| v <= 0.0) {
+            problems.push(("idle.blank_after", "must be positive".to_owned()));
+        }
+        if let (Some(dim), Some(blank)) = (self.dim_after, self.blank_after) {
+            if blank <= dim {
+                problems.push((
+                    "idle.blank_after",
+                    "must be greater than dim_after".to_owned(),
+                ));
+            }
+        }
+        if !(0.0..=100.0).contains(&self.dim_brightness) {
+            problems.push((
+                "idle.dim_brightness",
+                format!("{} is out of range 0..100%", self.dim_brightness),
+            ));
+        }
+        problems
+    }
+    fn target(&self, idle: f64) -> IdleState {
+        if self.blank_after.is_some_and(|t| idle >= t) {
+            IdleState::Blank
+        } else if self.dim_after.is_some_and(|t| idle >= t) {
+            IdleState::Dimmed
+        } else {
+            IdleState::Active
+        }
+    }
+}
+
+#[inline]
+fn default_logout_warn() -> f64 {
+    30.0
+}
+
+/// Times are in seconds
+#[derive(Deserialize, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct AutoLogoutConfig {
+    /// since the last user activity
+    after: f64,
+    /// the countdown warning is displayed the specified time before logout
+    #[serde(default = "default_logout_warn")]
+    warn: f64,
+    /// URL patterns (the same format as allowed_urls), the panel is not logged out on
+    #[serde(default)]
+    exempt_urls: HashSet<String>,
+}
+
+impl AutoLogoutConfig {
+    /// Returns a list of (key, problem)
+    pub fn validate(&self) -> Vec<(&'static str, String)> {
+        let mut problems = Vec::new();
+        if self.after <= 0.0 {
+            problems.push(("auto_logout.after", "must be positive".to_owned()));
+        }
+        if self.warn < 0.0 || self.warn >= self.after {
+            problems.push(("auto_logout.warn", "must be in range 0..after".to_owned()));
+        }
+        if let Err(e) = crate::policy::check_patterns(&self.exempt_urls) {
+            problems.push(("auto_logout.exempt_urls", e.to_string()));
+        }
+        problems
+    }
+}
+
+pub struct AutoLogout {
+    after: f64,
+    warn: f64,
+    exempt: UrlList,
+}
+
+impl AutoLogout {
+    pub fn new(config: &AutoLogoutConfig) -> EResult<Self> {
+        Ok(Self {
+            after: config.after,
+            warn: config.warn,
+            exempt: UrlList::new(&config.exempt_urls)?,
+        })
+    }
+}
+
+#[derive(bmart::tools::EnumStr, Serialize, Copy, Clone, Eq, PartialEq)]
+#[repr(u8)]
+#[serde(rename_all = "lowercase")]
+pub enum IdleState {
+    Active = 0,
+    Dimmed = 1,
+    Blank = 2,
+}
+
+impl From<u8> for IdleState {
+    fn from(code: u8) -> Self {
+        match code {
+            1 => IdleState::Dimmed,
+            2 => IdleState::Blank,
+            _ => IdleState::Active,
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct IdleInfo {
+    /// seconds since the last user activity
+    time: f64,
+    state: IdleState,
+}
+
+impl IdleInfo {
+    #[inline]
+    pub fn state(&self) -> IdleState {
+        self.state
+    }
+}
+
+pub fn set(config: Option<IdleConfig>) {
+    *CONFIG.write().unwrap() = config;
+}
+
+pub fn set_auto_logout(auto_logout: Option<AutoLogout>) {
+    *AUTO_LOGOUT.write().unwrap() = auto_logout.map(Arc::new);
+}
+
+pub fn logout_warning_js(seconds: Option<u64>) -> String {
+    format!(
+        "window.__evapanel_logout_warning && window.__evapanel_logout_warning({})",
+        seconds.map_or_else(|| "null".to_owned(), |v| v.to_string())
+    )
+}
+
+/// Called on any user input
+pub fn activity() {
+    *LAST_ACTIVITY.lock().unwrap() = Some(Instant::now());
+}
+
+fn idle_time() -> Duration {
+    LAST_ACTIVITY
+        .lock()
+        .unwrap()
+        .map_or(Duration::ZERO, |t| t.elapsed())
+}
+
+pub fn info() -> IdleInfo {
+    IdleInfo {
+        time: idle_time().as_secs_f64(),
+        state: STATE.load(atomic::Ordering::Relaxed).into(),
+    }
+}
+
+/// Dims and blanks the display when the user is idle, wakes it up on activity
+pub async fn run(proxy: EventLoopProxy<UEvent>) {
+    activity();
+    let mut state = IdleState::Active;
+    // the brightness before dimming, restored on wake up
+    let mut saved_brightness: Option<f32> = None;
+    loop {
+        tokio::time::sleep(CHECK_INTERVAL).await;
+        let target = CONFIG
+            .read()
+            .unwrap()
+            .as_ref()
+            .map_or(IdleState::Active, |c| c.target(idle_time().as_secs_f64()));
+        if target == state {
+            continue;
+        }
+        info!("idle state: {}", target);
+        match target {
+            IdleState::Active => {
+                if state == IdleState::Blank {
+                    crate::display::set_power(true).await.log_ef();
+                }
+                if let Some(brightness) = saved_brightness.take() {
+                    crate::display::set_brightness(brightness).await.log_ef();
+                }
+            }
+            IdleState::Dimmed => {
+                if state == IdleState::Blank {
+                    crate::display::set_power(true).await.log_ef();
+                } else {
+                    saved_brightness = Some(
+                        crate::display::state()
+                            .await
+                            .ok()
+                            .and_then(|s| s.brightness())
+                            .unwrap_or(DEFAULT_BRIGHTNESS),
+                    );
+                }
+                let dim_brightness = CONFIG
+                    .read()
+                    .unwrap()
+                    .as_ref()
+                    .map_or_else(default_dim_brightness, |c| c.dim_brightness);
+                crate::display::set_brightness(dim_brightness)
+                    .await
+                    .log_ef();
+            }
+            IdleState::Blank => {
+                // the page must swallow the first input before the screen goes dark
+                if proxy.send_event(UEvent::Sleep).is_err() {
+                    break;
+                }
+                crate::display::set_power(false).await.log_ef();
+            }
+        }
+        state = target;
+        STATE.store(state as u8, atomic::Ordering::Relaxed);
+    }
+}
+
+/// Asks the event loop for data
+async fn request<T>(
+    proxy: &EventLoopProxy<UEvent>,
+    event: impl FnOnce(async_channel::Sender<T>) -> UEvent,
+) -> Option<T> {
+    let (tx, rx) = async_channel::bounded(1);
+    proxy.send_event(event(tx)).ok()?;
+    tokio::time::timeout(REQUEST_TIMEOUT, rx.recv())
+        .await
+        .ok()?
+        .ok()
+}
+
+/// Warns with a countdown, then logs the user out and navigates to the home URL
+#[allow(clippy::cast_possible_truncation)]
+#[allow(clippy::cast_sign_loss)]
+pub async fn run_auto_logout(proxy: EventLoopProxy<UEvent>) {
+    let mut warning = false;
+    let mut done = false;
+    macro_rules! send_event {
+        ($event: expr) => {
+            if proxy.send_event($event).is_err() {
+                break;
+            }
+        };
+    }
+    macro_rules! hide_warning {
+        () => {
+            if warning {
+                send_event!(UEvent::LogoutWarning(None));
+                warning = false;
+            }
+        };
+    }
+    loop {
+        tokio::time::sleep(CHECK_INTERVAL).await;
+        let auto_logout = AUTO_LOGOUT.read().unwrap().clone();
+        let Some(auto_logout) = auto_logout else {
+            hide_warning!();
+            continue;
+        };
+        let idle = idle_time().as_secs_f64();
+        if idle < auto_logout.after - auto_logout.warn {
+            hide_warning!();
+            done = false;
+            continue;
+        }
+        if done {
+            continue;
+        }
+        if request(&proxy, UEvent::GetState).await != Some(State::Active) {
+            hide_warning!();
+            continue;
+        }
+        let url = request(&proxy, UEvent::GetLocation).await.flatten();
+        if url
+            .as_deref()
+            .is_some_and(|u| auto_logout.exempt.matches(u))
+        {
+            hide_warning!();
+            continue;
+        }
+        if idle >= auto_logout.after {
+            info!("auto-logout, idle for {:.0} sec", idle);
+            hide_warning!();
+            send_event!(UEvent::Logout);
+            send_event!(UEvent::Navigate(None));
+            crate::eapi::notify(PanelEvent::AutoLogout { url });
+            done = true;
+        } else if !warning {
+            let seconds = (auto_logout.after - idle).ceil() as u64;
+            info!("auto-logout in {} sec", seconds);
+            send_event!(UEvent::LogoutWarning(Some(seconds)));
+            warning = true;
+        }
+    }
+}
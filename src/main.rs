@@ -1,33 +1,47 @@
 use clap::Parser;
 use eva_common::{EResult, Error};
-use log::{debug, error, info};
+use log::{debug, error, info, warn};
 use once_cell::sync::OnceCell;
-use serde::Deserialize;
-use std::collections::HashSet;
 use std::fmt::Write as _;
-use std::sync::atomic;
+use std::sync::{atomic, RwLock};
 use std::thread;
 
 use tao::{
-    event_loop::{EventLoop, EventLoopBuilder},
-    window::{Fullscreen, Icon, WindowBuilder},
+    event_loop::{EventLoop, EventLoopBuilder, EventLoopProxy},
+    monitor::MonitorHandle,
+    window::{Fullscreen, Icon, Window, WindowBuilder},
 };
-use wry::WebViewBuilder;
+use wry::{WebView, WebViewBuilder};
 
+mod acl;
+mod bridge;
+mod commands;
 mod common;
+mod config;
+mod connectivity;
+mod display;
 mod eapi;
+#[cfg(target_os = "linux")]
+mod error_page;
 mod ev_loop;
+mod hang;
+mod idle;
+mod policy;
+mod protocol;
+mod schedule;
 
-use common::{BusConfig, PanelInfo, UEvent};
+use common::{MonitorSelector, PanelEvent, PanelInfo, UEvent};
+use config::Config;
 
 const APP_ICON: &[u8] = include_bytes!("../res/evapanel.rgba");
 
-static HOME_URL: OnceCell<String> = OnceCell::new();
-static ALLOWED_URLS: OnceCell<HashSet<String>> = OnceCell::new();
+static HOME_URL: RwLock<String> = RwLock::new(String::new());
+static CONFIG_PATH: OnceCell<String> = OnceCell::new();
 static MONITOR: OnceCell<String> = OnceCell::new();
-static REBOOT_CMD: OnceCell<String> = OnceCell::new();
 static ACTIVE: atomic::AtomicBool = atomic::AtomicBool::new(true);
 static DEBUG: atomic::AtomicBool = atomic::AtomicBool::new(false);
+static NAVIGATION_BLOCKED: atomic::AtomicU64 = atomic::AtomicU64::new(0);
+static RUNTIME: OnceCell<tokio::runtime::Handle> = OnceCell::new();
 const AGENT_NAME: &str = "EvaPanel";
 const VERSION: &str = env!("CARGO_PKG_VERSION");
 const ARCH: &str = include_str!("../res/compile-arch");
@@ -47,127 +61,189 @@ fn is_active() -> bool {
     ACTIVE.load(atomic::Ordering::Relaxed)
 }
 
+#[inline]
+fn home_url() -> String {
+    HOME_URL.read().unwrap().clone()
+}
+
 #[derive(Parser)]
 #[clap(author, version, about, long_about = None)]
 struct Args {
     #[clap(short = 'c', long = "config", default_value = "~/evapanel.yml")]
     config_path: String,
+    #[clap(long = "check-config", help = "Check the config file and exit")]
+    check_config: bool,
 }
 
-#[inline]
-fn default_title() -> String {
-    "EVA ICS Panel".to_owned()
-}
-
-#[inline]
-fn default_home_url() -> String {
-    "http://eva/ui/".to_owned()
-}
-
-fn default_window_size() -> (u32, u32) {
-    (800, 500)
+/// The window is placed on the monitor if specified, on the current one otherwise
+fn set_window_mode(
+    window: &Window,
+    fullscreen: bool,
+    window_size: (u32, u32),
+    monitor: Option<MonitorHandle>,
+) {
+    if fullscreen {
+        window.set_fullscreen(Some(Fullscreen::Borderless(monitor)));
+    } else {
+        window.set_fullscreen(None);
+        if let Some(monitor) = monitor {
+            window.set_outer_position(monitor.position());
+        }
+        window.set_inner_size(tao::dpi::LogicalSize::new(window_size.0, window_size.1));
+    }
 }
 
-#[inline]
-fn default_zoom() -> f64 {
-    1.0
+/// Finds the configured monitor, returns None if not configured or not found
+fn find_monitor(window: &Window, selector: Option<&MonitorSelector>) -> Option<MonitorHandle> {
+    let selector = selector?;
+    let monitor = window
+        .available_monitors()
+        .enumerate()
+        .find(|(index, m)| selector.matches(*index, m.name().as_deref()))
+        .map(|(_, m)| m);
+    if monitor.is_none() {
+        warn!("monitor {} not found, using the current one", selector);
+    }
+    monitor
 }
 
-#[inline]
-fn default_reboot_cmd() -> String {
-    "reboot".to_owned()
+/// Spawns a future in the background tasks runtime, e.g. from the event loop
+fn spawn<F>(future: F)
+where
+    F: std::future::Future<Output = ()> + Send + 'static,
+{
+    if let Some(rt) = RUNTIME.get() {
+        rt.spawn(future);
+    } else {
+        error!("the runtime is not started");
+    }
 }
 
-#[derive(Deserialize)]
-#[serde(deny_unknown_fields)]
-struct Config {
-    #[serde(default = "default_title")]
-    title: String,
-    #[serde(default = "default_home_url")]
-    home_url: String,
-    #[serde(default = "default_zoom")]
-    zoom: f64,
-    #[serde(default)]
-    engine: common::Engine,
-    #[serde(default)]
-    allowed_urls: HashSet<String>,
-    #[serde(default)]
-    fullscreen: bool,
-    #[serde(default = "default_window_size")]
-    window_size: (u32, u32),
-    #[serde(default)]
-    show_cursor: bool,
-    #[serde(default)]
-    debug: bool,
-    #[serde(default)]
-    sig: Option<String>,
-    #[serde(default)]
-    bus: Option<BusConfig>,
-    commands: Commands,
+/// Spawns background tasks, must be called inside the tokio runtime
+fn spawn_tasks(proxy: EventLoopProxy<UEvent>) {
+    let _ = RUNTIME.set(tokio::runtime::Handle::current());
+    tokio::spawn(display::apply_rotation());
+    tokio::spawn(schedule::run());
+    tokio::spawn(idle::run(proxy.clone()));
+    tokio::spawn(idle::run_auto_logout(proxy.clone()));
+    tokio::spawn(connectivity::run(proxy.clone()));
+    tokio::spawn(hang::run(proxy));
 }
 
-#[derive(Deserialize)]
-struct Commands {
-    #[serde(default = "default_reboot_cmd")]
-    reboot: String,
+/// Runs background tasks in a dedicated runtime, used if there is no bus runtime
+fn launch_tasks(proxy: EventLoopProxy<UEvent>) {
+    let rt = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .unwrap();
+    rt.block_on(async {
+        spawn_tasks(proxy);
+        std::future::pending::<()>().await;
+    });
 }
 
-impl Default for Config {
-    fn default() -> Self {
-        Self {
-            title: default_title(),
-            home_url: default_home_url(),
-            zoom: default_zoom(),
-            engine: <_>::default(),
-            allowed_urls: HashSet::new(),
-            fullscreen: false,
-            window_size: default_window_size(),
-            show_cursor: false,
-            debug: false,
-            sig: None,
-            bus: None,
-            commands: <_>::default(),
-        }
+fn navigation_allowed(url: String) -> bool {
+    if protocol::is_internal(&url) || policy::url_allowed(&url) {
+        true
+    } else {
+        warn!("navigation to {} blocked", url);
+        NAVIGATION_BLOCKED.fetch_add(1, atomic::Ordering::Relaxed);
+        eapi::notify(PanelEvent::NavigationBlocked { url });
+        false
     }
 }
 
-impl Default for Commands {
-    fn default() -> Self {
-        Self {
-            reboot: default_reboot_cmd(),
-        }
-    }
+/// Hides or shows the cursor over the page content, the window cursor setting is not applied to
+/// the Web view content on all platforms (e.g. on Wayland compositors)
+fn cursor_js(visible: bool) -> String {
+    format!(
+        r"(() => {{
+    const apply = () => {{
+      let style = document.getElementById('__evapanel_cursor');
+      if ({}) {{
+        if (style) style.remove();
+        return;
+      }}
+      if (style) return;
+      style = document.createElement('style');
+      style.id = '__evapanel_cursor';
+      style.textContent = '*, *::before, *::after {{ cursor: none !important; }}';
+      document.documentElement.appendChild(style);
+    }};
+    if (document.documentElement) {{
+      apply();
+    }} else {{
+      document.addEventListener('DOMContentLoaded', apply, {{ once: true }});
+    }}
+  }})();",
+        visible
+    )
 }
 
-#[inline]
-fn url_allowed(url: &str) -> bool {
-    for allowed_url in ALLOWED_URLS.get().unwrap() {
-        if url.starts_with(allowed_url) {
-            return true;
-        }
+/// Creates the Web view, also used to recreate it if the renderer hangs
+fn create_webview(
+    window: &Window,
+    config: &Config,
+    user_agent: &str,
+    proxy: EventLoopProxy<UEvent>,
+) -> EResult<WebView> {
+    let builder = WebViewBuilder::new()
+        .with_user_agent(user_agent)
+        .with_navigation_handler(navigation_allowed)
+        .with_on_page_load_handler(move |event, url| {
+            if matches!(event, wry::PageLoadEvent::Finished) {
+                // the cursor setting may have been changed after the Web view has been created
+                let _ = proxy.send_event(UEvent::PageLoaded);
+            }
+            eapi::notify(PanelEvent::Page {
+                status: event.into(),
+                url,
+            });
+        })
+        .with_custom_protocol(protocol::SCHEME.to_owned(), |_id, request| {
+            protocol::handle(&request)
+        })
+        .with_initialization_script(idle::ACTIVITY_JS)
+        .with_initialization_script(connectivity::OFFLINE_JS)
+        .with_initialization_script(bridge::BRIDGE_JS)
+        .with_initialization_script(&cursor_js(config.show_cursor))
+        .with_ipc_handler(|request| {
+            let body = request.body();
+            if body == idle::ACTIVITY_MESSAGE {
+                idle::activity();
+            } else {
+                bridge::handle(&request.uri().to_string(), body);
+            }
+        })
+        .with_url(&config.home_url)
+        .with_devtools(config.debug);
+
+    #[cfg(target_os = "windows")]
+    let webview = builder.build(window).map_err(Error::failed)?;
+    #[cfg(target_os = "linux")]
+    let webview = {
+        use tao::platform::unix::WindowExtUnix;
+        use wry::WebViewBuilderExtUnix;
+        let vbox = window.default_vbox().unwrap();
+        builder.build_gtk(vbox).map_err(Error::failed)?
+    };
+    #[cfg(target_os = "linux")]
+    error_page::attach(&webview);
+    if let Err(e) = webview.zoom(config.zoom) {
+        error!("zoom error: {}", e);
     }
-    false
+    Ok(webview)
 }
 
 #[allow(clippy::too_many_lines)]
 fn main() -> EResult<()> {
     let args = Args::parse();
-    let (config, used_default) = match std::fs::read(shellexpand::tilde(&args.config_path).as_ref())
-    {
-        Ok(v) => (
-            serde_yaml::from_slice(&v).map_err(|e| {
-                Error::invalid_data(format!("Unable to parse {}: {}", args.config_path, e))
-            })?,
-            false,
-        ),
-        Err(e) if e.kind() == std::io::ErrorKind::NotFound => (Config::default(), true),
-        Err(e) => {
-            return Err(Error::io(format!(
-                "Unable to open {}: {}",
-                args.config_path, e
-            )))
-        }
-    };
+    if args.check_config {
+        return config::check(&args.config_path);
+    }
+    let (mut config, used_default) = Config::load(&args.config_path)?;
+    // must be set before any thread, which may reload or save the config, is spawned
+    CONFIG_PATH.set(args.config_path.clone()).unwrap();
     env_logger::Builder::new()
         .target(env_logger::Target::Stdout)
         .filter_level(if config.debug {
@@ -184,34 +260,52 @@ fn main() -> EResult<()> {
             &args.config_path
         }
     );
+    for (key, problem) in config.validate() {
+        warn!("config {}: {}", key, problem);
+    }
     let mut user_agent = format!(
         "{} {} {}/{} ({})",
         AGENT_NAME, VERSION, ARCH, config.engine, WEB_ENGINE
     );
-    if let Some(sig) = config.sig {
+    if let Some(ref sig) = config.sig {
         write!(user_agent, " {}", sig).map_err(Error::failed)?;
     }
-    let allow_any = config.allowed_urls.contains("*");
-    let mut allowed_urls = config.allowed_urls;
-    allowed_urls.insert(config.home_url.clone());
     info!("starting {}", user_agent);
     debug!("home_url: {}", config.home_url);
     debug!("zoom: {}", config.zoom);
     debug!("engine: {}", config.engine);
     debug!(
         "allow urls: {}",
-        allowed_urls
+        config
+            .allowed_urls
             .iter()
             .map(String::as_str)
             .collect::<Vec<&str>>()
             .join(", ")
     );
-    debug!("reboot_cmd: {}", config.commands.reboot);
     debug!("user agent: {}", user_agent);
-    debug!("allow any: {}", allow_any);
-    HOME_URL.set(config.home_url.clone()).unwrap();
-    REBOOT_CMD.set(config.commands.reboot).unwrap();
-    ALLOWED_URLS.set(allowed_urls).unwrap();
+    debug!(
+        "deny urls: {}",
+        config
+            .denied_urls
+            .iter()
+            .map(String::as_str)
+            .collect::<Vec<&str>>()
+            .join(", ")
+    );
+    debug!(
+        "allow any: {}",
+        config.allowed_urls.contains(policy::ALLOW_ANY)
+    );
+    acl::set(config.acl.clone());
+    *HOME_URL.write().unwrap() = config.home_url.clone();
+    commands::set(config.commands.clone());
+    protocol::set_pages_dir(config.pages_dir.as_deref());
+    policy::init(policy::Policy::new(
+        &config.home_url,
+        &config.allowed_urls,
+        &config.denied_urls,
+    )?);
     let event_loop: EventLoop<UEvent> = EventLoopBuilder::with_user_event().build();
     info!("creating HMI window");
     let window = WindowBuilder::new()
@@ -222,44 +316,55 @@ fn main() -> EResult<()> {
         window.set_window_icon(Some(icon));
     }
     window.set_cursor_visible(config.show_cursor);
-    if config.fullscreen {
-        window.set_fullscreen(Some(Fullscreen::Borderless(None)));
-    } else {
-        window.set_inner_size(tao::dpi::LogicalSize::new(
-            config.window_size.0,
-            config.window_size.1,
-        ));
-    }
+    let target_monitor = find_monitor(&window, config.monitor.as_ref());
+    set_window_mode(
+        &window,
+        config.fullscreen,
+        config.window_size,
+        target_monitor.clone(),
+    );
     #[cfg(target_os = "linux")]
-    if let Some(monitor) = window.current_monitor().and_then(|v| v.name()) {
+    if let Some(monitor) = target_monitor
+        .or_else(|| window.current_monitor())
+        .and_then(|v| v.name())
+    {
         info!("monitor: {}", monitor);
         MONITOR.set(monitor).unwrap();
     }
+    let monitor_set = config.monitor.is_some();
+    if let Some(ref mut bus) = config.bus {
+        bus.resolve(monitor_set)?;
+        info!("bus name: {}, path: {}", bus.name(), bus.path());
+    }
+    if let Err(e) = display::init(&config.display) {
+        error!("display backend error: {}", e);
+    }
+    schedule::set(config.display_schedule.clone());
+    idle::set(config.idle.clone());
+    idle::set_auto_logout(
+        config
+            .auto_logout
+            .as_ref()
+            .map(idle::AutoLogout::new)
+            .transpose()?,
+    );
+    connectivity::set(config.connectivity.clone());
+    hang::set(config.hang_watchdog.clone());
+    bridge::set(
+        config
+            .bridge
+            .as_ref()
+            .map(bridge::Bridge::new)
+            .transpose()?,
+    );
+    bridge::init(event_loop.create_proxy());
     info!("creating Web view");
-    let builder = WebViewBuilder::new()
-        .with_user_agent(&user_agent)
-        .with_navigation_handler(move |url| allow_any || url_allowed(&url))
-        .with_url(&config.home_url)
-        .with_devtools(config.debug);
-
-    #[cfg(target_os = "windows")]
-    let webview = builder.build(&window).map_err(Error::failed)?;
-    #[cfg(target_os = "linux")]
-    let webview = {
-        use tao::platform::unix::WindowExtUnix;
-        use wry::WebViewBuilderExtUnix;
-        let vbox = window.default_vbox().unwrap();
-        builder.build_gtk(vbox).map_err(Error::failed)?
-    };
-
+    let webview = create_webview(&window, &config, &user_agent, event_loop.create_proxy())?;
     DEBUG.store(config.debug, atomic::Ordering::Relaxed);
-    if let Err(e) = webview.zoom(config.zoom) {
-        error!("zoom error: {}", e);
-    }
     info!("starting event loop");
     if let Some(ref bus) = config.bus {
         let panel_info = PanelInfo {
-            home_url: config.home_url,
+            bus_name: bus.name().to_owned(),
             agent: AGENT_NAME.to_owned(),
             version: VERSION.to_owned(),
             engine: config.engine,
@@ -271,7 +376,14 @@ fn main() -> EResult<()> {
         thread::spawn(move || {
             eapi::launch(&bus_c, api_proxy, panel_info);
         });
+    } else {
+        let tasks_proxy = event_loop.create_proxy();
+        thread::spawn(move || launch_tasks(tasks_proxy));
     }
-    ev_loop::run(event_loop, webview, config.debug, config.bus);
+    let watch_proxy = event_loop.create_proxy();
+    thread::spawn(move || {
+        config::watch(&args.config_path, &watch_proxy);
+    });
+    ev_loop::run(event_loop, window, webview, config, user_agent);
     Ok(())
 }
@@ -0,0 +1,712 @@
+use eva_common::{EResult, Error};
+use once_cell::sync::OnceCell;
+use regex::Regex;
+use serde::Serialize;
+use std::collections::{BTreeMap, HashSet};
+use std::sync::RwLock;
+use url::Url;
+
+/// allows any URL if present in the allowed list
+pub const ALLOW_ANY: &str = "*";
+/// entries with this prefix are regular expressions, which must match the whole URL
+pub const REGEX_PREFIX: &str = "re:";
+
+const ALLOWED_KEY: &str = "allowed_urls";
+const DENIED_KEY: &str = "denied_urls";
+
+static POLICY: OnceCell<RwLock<Policy>> = OnceCell::new();
+
+#[derive(Debug)]
+enum Host {
+    Any,
+    Exact(String),
+    /// sub-domains of the domain, contains the leading dot
+    Suffix(String),
+}
+
+#[derive(Debug)]
+enum Port {
+    /// the default port of the URL scheme
+    Default,
+    Any,
+    Exact(u16),
+}
+
+#[derive(Debug)]
+struct UrlPattern {
+    scheme: Option<String>,
+    host: Host,
+    port: Port,
+    path: String,
+}
+
+/// URL pattern formats:
+///
+/// * "*" - any URL
+/// * "re:REGEX" - a regular expression, anchored to match the whole URL
+/// * "SCHEME://HOST[:PORT][/PATH]" - SCHEME and PORT can be "*" for any, HOST can be "*" for
+///   any or "*.domain" for sub-domains, PATH is matched as a prefix by path segments. If the
+///   port is not specified, the default port of the URL scheme is required
+/// * anything else (e.g. "about:blank") - a plain URL prefix
+#[derive(Debug)]
+enum Rule {
+    Any,
+    Regex(Regex),
+    Url(UrlPattern),
+    Prefix(String),
+}
+
+impl Rule {
+    fn parse(pattern: &str) -> EResult<Self> {
+        if pattern == ALLOW_ANY {
+            return Ok(Rule::Any);
+        }
+        if let Some(re) = pattern.strip_prefix(REGEX_PREFIX) {
+            // an unanchored regex would match e.g. a query string of any URL
+            return Regex::new(&format!("^(?:{})$", re))
+                .map(Rule::Regex)
+                .map_err(|e| Error::invalid_params(format!("invalid regex {}: {}", re, e)));
+        }
+        let Some((scheme, rest)) = pattern.split_once("://") else {
+            return Ok(Rule::Prefix(pattern.to_owned()));
+        };
+        let scheme = if scheme == "*" {
+            None
+        } else {
+            Some(scheme.to_ascii_lowercase())
+        };
+        let (host_port, path) = rest.find('/').map_or((rest, ""), |pos| rest.split_at(pos));
+        // the query and the fragment are not matched
+        let path = path.split(['?', '#']).next().unwrap_or_default();
+        let (host, port) = if host_port.starts_with('[') {
+            // IPv6 address
+            let Some(pos) = host_port.find(']') else {
+                return Err(Error::invalid_params(format!(
+                    "invalid URL pattern: {}",
+                    pattern
+                )));
+            };
+            let (host, port) = host_port.split_at(pos + 1);
+            (host, port.strip_prefix(':'))
+        } else {
+            match host_port.rsplit_once(':') {
+                Some((host, port)) => (host, Some(port)),
+                None => (host_port, None),
+            }
+        };
+        let host = if host == "*" {
+            Host::Any
+        } else if let Some(domain) = host.strip_prefix('*') {
+            if !domain.starts_with('.') || domain.contains('*') {
+                return Err(Error::invalid_params(format!(
+                    "invalid host wildcard: {}",
+                    pattern
+                )));
+            }
+            Host::Suffix(domain.to_ascii_lowercase())
+        } else if host.contains('*') {
+            return Err(Error::invalid_params(format!(
+                "invalid host wildcard: {}",
+                pattern
+            )));
+        } else {
+            Host::Exact(host.to_ascii_lowercase())
+        };
+        let port = match port {
+            None => Port::Default,
+            Some("*") => Port::Any,
+            Some(v) => Port::Exact(
+                v.parse()
+                    .map_err(|_| Error::invalid_params(format!("invalid port: {}", pattern)))?,
+            ),
+        };
+        Ok(Rule::Url(UrlPattern {
+            scheme,
+            host,
+            port,
+            path: path.to_owned(),
+        }))
+    }
+
+    fn matches(&self, raw: &str, url: Option<&Url>) -> bool {
+        match self {
+            Rule::Any => true,
+            Rule::Regex(re) => re.is_match(raw),
+            Rule::Prefix(prefix) => raw.starts_with(prefix),
+            Rule::Url(pattern) => url.is_some_and(|u| pattern.matches(u)),
+        }
+    }
+}
+
+impl UrlPattern {
+    fn matches(&self, url: &Url) -> bool {
+        if let Some(ref scheme) = self.scheme {
+            if url.scheme() != scheme {
+                return false;
+            }
+        }
+        let host = url.host_str().unwrap_or_default().to_ascii_lowercase();
+        let host_matches = match self.host {
+            Host::Any => true,
+            Host::Exact(ref h) => host == *h,
+            Host::Suffix(ref domain) => host.len() > domain.len() && host.ends_with(domain),
+        };
+        if !host_matches {
+            return false;
+        }
+        let port_matches = match self.port {
+            Port::Default => url.port().is_none(),
+            Port::Any => true,
+            Port::Exact(port) => url.port_or_known_default() == Some(port),
+        };
+        port_matches && path_matches(url.path(), &self.path)
+    }
+}
+
+/// Matches the path prefix by segments, e.g. "/ui" matches "/ui" and "/ui/x" but not "/uix"
+fn path_matches(path: &str, prefix: &str) -> bool {
+    if prefix.is_empty() || path == prefix {
+        return true;
+    }
+    if prefix.ends_with('/') {
+        path.starts_with(prefix)
+    } else {
+        path.strip_prefix(prefix)
+            .is_some_and(|rest| rest.starts_with('/'))
+    }
+}
+
+fn compile(patterns: &HashSet<String>) -> EResult<BTreeMap<String, Rule>> {
+    patterns
+        .iter()
+        .map(|p| Ok((p.clone(), Rule::parse(p)?)))
+        .collect()
+}
+
+pub fn check_patterns(patterns: &HashSet<String>) -> EResult<()> {
+    for pattern in patterns {
+        Rule::parse(pattern)?;
+    }
+    Ok(())
+}
+
+/// A list of URL patterns, e.g. auto-logout exemptions
+#[derive(Debug)]
+pub struct UrlList(Vec<Rule>);
+
+impl UrlList {
+    pub fn new(patterns: &HashSet<String>) -> EResult<Self> {
+        patterns
+            .iter()
+            .map(|p| Rule::parse(p))
+            .collect::<EResult<_>>()
+            .map(Self)
+    }
+    pub fn matches(&self, raw: &str) -> bool {
+        let url = Url::parse(raw).ok();
+        self.0.iter().any(|r| r.matches(raw, url.as_ref()))
+    }
+}
+
+/// The home URL rule, matches the home URL origin and path (with sub-paths), the query and the
+/// fragment are ignored
+fn home_rule(home_url: &str) -> EResult<Rule> {
+    let url = Url::parse(home_url)
+        .map_err(|e| Error::invalid_params(format!("invalid home URL {}: {}", home_url, e)))?;
+    let Some(host) = url.host_str() else {
+        let mut url = url;
+        url.set_query(None);
+        url.set_fragment(None);
+        return Ok(Rule::Prefix(url.to_string()));
+    };
+    Ok(Rule::Url(UrlPattern {
+        scheme: Some(url.scheme().to_owned()),
+        host: Host::Exact(host.to_ascii_lowercase()),
+        port: url.port().map_or(Port::Default, Port::Exact),
+        path: url.path().to_owned(),
+    }))
+}
+
+/// A runtime list change, which has not been saved to the config file
+#[derive(Debug, Clone)]
+struct Change {
+    pattern: String,
+    deny: bool,
+    add: bool,
+}
+
+#[derive(Debug)]
+pub struct Policy {
+    home_url: String,
+    home: Rule,
+    allowed: BTreeMap<String, Rule>,
+    denied: BTreeMap<String, Rule>,
+    /// kept on config reload until saved
+    unsaved: Vec<Change>,
+}
+
+impl Policy {
+    pub fn new(
+        home_url: &str,
+        allowed: &HashSet<String>,
+        denied: &HashSet<String>,
+    ) -> EResult<Self> {
+        Ok(Self {
+            home_url: home_url.to_owned(),
+            home: home_rule(home_url)?,
+            allowed: compile(allowed)?,
+            denied: compile(denied)?,
+            unsaved: Vec::new(),
+        })
+    }
+    fn rules_mut(&mut self, deny: bool) -> &mut BTreeMap<String, Rule> {
+        if deny {
+            &mut self.denied
+        } else {
+            &mut self.allowed
+        }
+    }
+    fn apply(&mut self, change: Change) -> EResult<bool> {
+        let changed = if change.add {
+            let rule = Rule::parse(&change.pattern)?;
+            self.rules_mut(change.deny)
+                .insert(change.pattern.clone(), rule)
+                .is_none()
+        } else {
+            self.rules_mut(change.deny)
+                .remove(&change.pattern)
+                .is_some()
+        };
+        if changed {
+            self.unsaved.push(change);
+        }
+        Ok(changed)
+    }
+    /// Denied entries have priority, the home URL and its sub-urls are allowed if not denied
+    fn url_allowed(&self, raw: &str) -> bool {
+        let url = Url::parse(raw).ok();
+        if self.denied.values().any(|r| r.matches(raw, url.as_ref())) {
+            return false;
+        }
+        self.home.matches(raw, url.as_ref())
+            || self.allowed.values().any(|r| r.matches(raw, url.as_ref()))
+    }
+}
+
+#[derive(Serialize)]
+pub struct PolicyInfo {
+    home_url: String,
+    allow_any: bool,
+    urls: Vec<String>,
+    denied_urls: Vec<String>,
+}
+
+#[inline]
+fn policy() -> &'static RwLock<Policy> {
+    POLICY.get().unwrap()
+}
+
+pub fn init(policy: Policy) {
+    POLICY.set(RwLock::new(policy)).unwrap();
+}
+
+/// Replaces the policy, e.g. on config reload. Unsaved runtime list changes are applied to the
+/// new policy
+pub fn set(mut new_policy: Policy) {
+    let mut policy = policy().write().unwrap();
+    for change in std::mem::take(&mut policy.unsaved) {
+        // the patterns have been already parsed
+        let _ = new_policy.apply(change);
+    }
+    *policy = new_policy;
+}
+
+pub fn url_allowed(url: &str) -> bool {
+    policy().read().unwrap().url_allowed(url)
+}
+
+pub fn info() -> PolicyInfo {
+    let policy = policy().read().unwrap();
+    PolicyInfo {
+        home_url: policy.home_url.clone(),
+        allow_any: policy.allowed.contains_key(ALLOW_ANY),
+        urls: policy
+            .allowed
+            .keys()
+            .filter(|v| *v != ALLOW_ANY)
+            .cloned()
+            .collect(),
+        denied_urls: policy.denied.keys().cloned().collect(),
+    }
+}
+
+/// Returns true if the policy has been changed
+pub fn add(pattern: String, deny: bool) -> EResult<bool> {
+    policy().write().unwrap().apply(Change {
+        pattern,
+        deny,
+        add: true,
+    })
+}
+
+/// Returns true if the policy has been changed
+pub fn remove(pattern: &str, deny: bool) -> bool {
+    policy()
+        .write()
+        .unwrap()
+        .apply(Change {
+            pattern: pattern.to_owned(),
+            deny,
+            add: false,
+        })
+        .unwrap_or_default()
+}
+
+/// Returns true if the policy has been changed
+pub fn set_allow_any(allow: bool) -> bool {
+    if allow {
+        add(ALLOW_ANY.to_owned(), false).unwrap()
+    } else {
+        remove(ALLOW_ANY, false)
+    }
+}
+
+/// Replaces a top-level block key in the YAML source, the rest of the source (including
+/// comments) is kept as-is. The key is removed if the value is None, appended if not found
+fn set_yaml_key(source: &str, key: &str, value: Option<&[String]>) -> EResult<String> {
+    let block = value
+        .map(|v| {
+            let mut map = serde_yaml::Mapping::new();
+            map.insert(key.into(), serde_yaml::to_value(v)?);
+            serde_yaml::to_string(&map)
+        })
+        .transpose()
+        .map_err(Error::failed)?;
+    let lines: Vec<&str> = source.lines().collect();
+    let start = lines.iter().position(|line| {
+        line.strip_prefix(key)
+            .is_some_and(|rest| rest.trim_start().starts_with(':'))
+    });
+    let mut result = String::with_capacity(source.len());
+    if let Some(start) = start {
+        // the block ends with the last indented or sequence line before the next key
+        let mut end = start;
+        for (n, line) in lines.iter().enumerate().skip(start + 1) {
+            let trimmed = line.trim_start();
+            if trimmed.is_empty() || (trimmed.starts_with('#') && trimmed.len() == line.len()) {
+                continue;
+            }
+            if line.starts_with(char::is_whitespace) || *line == "-" || line.starts_with("- ") {
+                end = n;
+            } else {
+                break;
+            }
+        }
+        for line in &lines[..start] {
+            result.push_str(line);
+            result.push('\n');
+        }
+        if let Some(block) = block {
+            result.push_str(&block);
+        }
+        for line in &lines[end + 1..] {
+            result.push_str(line);
+            result.push('\n');
+        }
+    } else {
+        result.push_str(source);
+        if !result.is_empty() && !result.ends_with('\n') {
+            result.push('\n');
+        }
+        if let Some(block) = block {
+            result.push_str(&block);
+        }
+    }
+    // the result must differ from the source in the key value only
+    let parse = |s: &str| -> EResult<serde_yaml::Mapping> {
+        match serde_yaml::from_str(s).map_err(Error::invalid_data)? {
+            serde_yaml::Value::Null => Ok(serde_yaml::Mapping::new()),
+            serde_yaml::Value::Mapping(m) => Ok(m),
+            _ => Err(Error::invalid_data("the config is not a mapping")),
+        }
+    };
+    let mut expected = parse(source)?;
+    if let Some(v) = value {
+        expected.insert(key.into(), serde_yaml::to_value(v).map_err(Error::failed)?);
+    } else {
+        expected.remove(key);
+    }
+    if parse(&result)? != expected {
+        return Err(Error::unsupported(format!(
+            "unable to update {}, the YAML format is not supported",
+            key
+        )));
+    }
+    Ok(result)
+}
+
+/// Writes the current allowed and denied URL lists back to the config file, only the list keys
+/// are updated, the file is created if does not exist
+pub async fn save(config_path: &str) -> EResult<()> {
+    let (allowed, denied, saved): (Vec<String>, Vec<String>, usize) = {
+        let policy = policy().read().unwrap();
+        (
+            policy.allowed.keys().cloned().collect(),
+            policy.denied.keys().cloned().collect(),
+            policy.unsaved.len(),
+        )
+    };
+    let path = shellexpand::tilde(config_path).into_owned();
+    let source = match tokio::fs::read_to_string(&path).await {
+        Ok(v) => v,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => String::new(),
+        Err(e) => return Err(Error::io(format!("Unable to open {}: {}", config_path, e))),
+    };
+    let data = set_yaml_key(&source, ALLOWED_KEY, Some(&allowed))
+        .and_then(|s| {
+            set_yaml_key(
+                &s,
+                DENIED_KEY,
+                (!denied.is_empty()).then_some(denied.as_slice()),
+            )
+        })
+        .map_err(|e| Error::invalid_data(format!("{}: {}", config_path, e)))?;
+    let tmp_path = format!("{}.tmp", path);
+    tokio::fs::write(&tmp_path, data)
+        .await
+        .map_err(|e| Error::io(format!("Unable to write {}: {}", tmp_path, e)))?;
+    tokio::fs::rename(&tmp_path, &path)
+        .await
+        .map_err(|e| Error::io(format!("Unable to write {}: {}", config_path, e)))?;
+    // changes, made while saving, are kept
+    let mut policy = policy().write().unwrap();
+    let saved = saved.min(policy.unsaved.len());
+    policy.unsaved.drain(..saved);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn set(patterns: &[&str]) -> HashSet<String> {
+        patterns.iter().map(|v| (*v).to_owned()).collect()
+    }
+
+    fn policy(home_url: &str, allowed: &[&str], denied: &[&str]) -> Policy {
+        Policy::new(home_url, &set(allowed), &set(denied)).unwrap()
+    }
+
+    fn rule_matches(pattern: &str, raw: &str) -> bool {
+        Rule::parse(pattern)
+            .unwrap()
+            .matches(raw, Url::parse(raw).ok().as_ref())
+    }
+
+    #[test]
+    fn test_path_matches() {
+        assert!(path_matches("/ui", ""));
+        assert!(path_matches("/ui", "/ui"));
+        assert!(path_matches("/ui/x", "/ui"));
+        assert!(!path_matches("/uix", "/ui"));
+        assert!(path_matches("/ui/", "/ui/"));
+        assert!(path_matches("/ui/x/y", "/ui/"));
+        assert!(!path_matches("/ui", "/ui/"));
+        assert!(!path_matches("/other", "/ui"));
+    }
+
+    #[test]
+    fn test_host_suffix() {
+        assert!(rule_matches(
+            "https://*.plant.local",
+            "https://hmi.plant.local/"
+        ));
+        assert!(rule_matches(
+            "https://*.plant.local",
+            "https://a.b.plant.local/x"
+        ));
+        assert!(!rule_matches(
+            "https://*.plant.local",
+            "https://plant.local/"
+        ));
+        assert!(!rule_matches(
+            "https://*.plant.local",
+            "https://evilplant.local/"
+        ));
+        assert!(!rule_matches(
+            "https://*.plant.local",
+            "https://plant.local.evil/"
+        ));
+        assert!(Rule::parse("https://*plant.local").is_err());
+        assert!(Rule::parse("https://hmi.*.local").is_err());
+    }
+
+    #[test]
+    fn test_ports() {
+        // Port::Default
+        assert!(rule_matches("http://eva/", "http://eva/ui/"));
+        assert!(rule_matches("http://eva/", "http://eva:80/ui/"));
+        assert!(!rule_matches("http://eva/", "http://eva:8080/ui/"));
+        // Port::Any
+        assert!(rule_matches("http://eva:*/", "http://eva:8080/"));
+        assert!(rule_matches("http://eva:*/", "http://eva/"));
+        // Port::Exact
+        assert!(rule_matches("http://eva:8080/", "http://eva:8080/ui/"));
+        assert!(!rule_matches("http://eva:8080/", "http://eva/ui/"));
+        assert!(rule_matches("https://eva:443/", "https://eva/"));
+        assert!(Rule::parse("http://eva:port/").is_err());
+    }
+
+    #[test]
+    fn test_ipv6() {
+        assert!(rule_matches("http://[::1]/ui", "http://[::1]/ui/x"));
+        assert!(rule_matches("http://[::1]:8080/", "http://[::1]:8080/"));
+        assert!(!rule_matches("http://[::1]:8080/", "http://[::1]/"));
+        assert!(!rule_matches("http://[::1]/", "http://[::2]/"));
+        assert!(Rule::parse("http://[::1/").is_err());
+    }
+
+    #[test]
+    fn test_scheme() {
+        assert!(rule_matches("*://eva/", "https://eva/"));
+        assert!(rule_matches("*://eva/", "http://eva/"));
+        assert!(!rule_matches("https://eva/", "http://eva/"));
+    }
+
+    #[test]
+    fn test_regex_anchored() {
+        assert!(rule_matches(
+            r"re:http://eva\.local/.*",
+            "http://eva.local/ui/"
+        ));
+        assert!(!rule_matches(
+            r"re:eva\.local",
+            "http://attacker/?eva.local"
+        ));
+        assert!(!rule_matches(
+            r"re:http://eva\.local/",
+            "http://eva.local/x"
+        ));
+        assert!(Rule::parse("re:(").is_err());
+    }
+
+    #[test]
+    fn test_host_attacker() {
+        let p = policy("http://eva/ui/", &["http://eva"], &[]);
+        assert!(p.url_allowed("http://eva/"));
+        assert!(p.url_allowed("http://eva/ui/"));
+        assert!(!p.url_allowed("http://eva.attacker.example/"));
+        assert!(!p.url_allowed("http://eva.attacker.example/ui/"));
+        assert!(!p.url_allowed("http://attacker.example/http://eva/"));
+    }
+
+    #[test]
+    fn test_deny_priority() {
+        let p = policy("http://eva/ui/", &["*"], &["http://eva/ui/admin"]);
+        assert!(p.url_allowed("http://other/"));
+        assert!(p.url_allowed("http://eva/ui/"));
+        assert!(!p.url_allowed("http://eva/ui/admin"));
+        assert!(!p.url_allowed("http://eva/ui/admin/users"));
+        assert!(p.url_allowed("http://eva/ui/administrator"));
+    }
+
+    #[test]
+    fn test_home_url() {
+        let p = policy("http://eva/ui/#/dashboard", &[], &[]);
+        assert!(p.url_allowed("http://eva/ui/#/dashboard"));
+        assert!(p.url_allowed("http://eva/ui/#/other"));
+        assert!(p.url_allowed("http://eva/ui/"));
+        assert!(!p.url_allowed("http://eva/"));
+        let p = policy("http://eva:8080/ui/?kiosk=1", &[], &[]);
+        assert!(p.url_allowed("http://eva:8080/ui/?kiosk=1"));
+        assert!(p.url_allowed("http://eva:8080/ui/page"));
+        assert!(!p.url_allowed("http://eva/ui/?kiosk=1"));
+        assert!(!p.url_allowed("http://eva.attacker.example:8080/ui/"));
+    }
+
+    #[test]
+    fn test_pattern_query() {
+        assert!(rule_matches("http://eva/ui/?kiosk=1", "http://eva/ui/x"));
+    }
+
+    #[test]
+    fn test_unsaved_changes() {
+        // the only test, which uses the global policy
+        if let Err(p) = POLICY.set(RwLock::new(policy("http://eva/ui/", &[], &[]))) {
+            *super::policy().write().unwrap() = p.into_inner().unwrap();
+        }
+        assert!(add("http://hmi/".to_owned(), false).unwrap());
+        assert!(add("http://eva/ui/admin".to_owned(), true).unwrap());
+        assert!(!add("http://hmi/".to_owned(), false).unwrap());
+        // the config has been reloaded
+        super::set(policy("http://eva/ui/", &["http://other/"], &[]));
+        assert!(url_allowed("http://hmi/"));
+        assert!(url_allowed("http://other/"));
+        assert!(!url_allowed("http://eva/ui/admin"));
+        assert_eq!(super::policy().read().unwrap().unsaved.len(), 2);
+        // removed entries stay removed after the next reload
+        assert!(remove("http://hmi/", false));
+        super::set(policy("http://eva/ui/", &["http://hmi/"], &[]));
+        assert!(!url_allowed("http://hmi/"));
+        assert!(!url_allowed("http://eva/ui/admin"));
+    }
+
+    #[test]
+    fn test_set_yaml_key() {
+        let urls = ["http://hmi/".to_owned(), "re:http://x/.*".to_owned()];
+        let source = "# the panel config
+title: Panel # the window title
+allowed_urls:
+  # trusted hosts
+  - http://old/
+
+  - http://old2/
+# network settings
+bus:
+  path: /tmp/evapanel.sock
+denied_urls: [http://a/]
+";
+        let result = set_yaml_key(source, ALLOWED_KEY, Some(&urls)).unwrap();
+        let result = set_yaml_key(&result, DENIED_KEY, None).unwrap();
+        assert!(result.starts_with("# the panel config\ntitle: Panel # the window title\n"));
+        assert!(result.contains("# network settings\nbus:\n  path: /tmp/evapanel.sock\n"));
+        assert!(!result.contains("old"));
+        assert!(!result.contains(DENIED_KEY));
+        let config: serde_yaml::Value = serde_yaml::from_str(&result).unwrap();
+        assert_eq!(
+            config[ALLOWED_KEY],
+            serde_yaml::to_value(urls.as_slice()).unwrap()
+        );
+        assert_eq!(config["bus"]["path"], "/tmp/evapanel.sock");
+        // sequences at the key indentation
+        let result = set_yaml_key(
+            "allowed_urls:\n- http://old/\n- http://old2/\nzoom: 1.5",
+            ALLOWED_KEY,
+            Some(&urls[..1]),
+        )
+        .unwrap();
+        assert_eq!(result, "allowed_urls:\n- http://hmi/\nzoom: 1.5\n");
+        // missing key and an empty (missing) file
+        assert_eq!(
+            set_yaml_key("zoom: 1.5", ALLOWED_KEY, Some(&urls[..1])).unwrap(),
+            "zoom: 1.5\nallowed_urls:\n- http://hmi/\n"
+        );
+        assert_eq!(
+            set_yaml_key("", ALLOWED_KEY, Some(&urls[..1])).unwrap(),
+            "allowed_urls:\n- http://hmi/\n"
+        );
+        assert_eq!(
+            set_yaml_key("zoom: 1.5\n", DENIED_KEY, None).unwrap(),
+            "zoom: 1.5\n"
+        );
+        // unsupported formats are never written
+        assert!(set_yaml_key("{allowed_urls: [a], zoom: 1}", ALLOWED_KEY, Some(&urls)).is_err());
+        assert!(set_yaml_key("- a\n- b\n", ALLOWED_KEY, Some(&urls)).is_err());
+    }
+
+    #[test]
+    fn test_prefix() {
+        assert!(rule_matches("about:blank", "about:blank"));
+        assert!(!rule_matches("about:blank", "about:config"));
+    }
+}
@@ -0,0 +1,340 @@
+use base64::Engine as _;
+use log::{debug, warn};
+use once_cell::sync::Lazy;
+use std::borrow::Cow;
+use std::path::{Component, Path, PathBuf};
+use std::sync::RwLock;
+use url::Url;
+use wry::http::{header::CONTENT_TYPE, Request, Response, StatusCode};
+
+/// The internal URL scheme, on Windows the URLs are served as http://evapanel.HOST/PATH
+pub const SCHEME: &str = "evapanel";
+/// bundled resources: evapanel://app/PAGE
+const APP_HOST: &str = "app";
+/// files of the local pages directory: evapanel://local/PATH
+const LOCAL_HOST: &str = "local";
+const INDEX: &str = "index.html";
+
+const LOGO: &[u8] = include_bytes!("../assets/evapanel.png");
+
+/// Bundled resources, HTML pages are rendered with placeholders
+const RESOURCES: &[(&str, &[u8])] = &[
+    ("splash.html", include_bytes!("../assets/splash.html")),
+    ("error.html", include_bytes!("../assets/error.html")),
+    ("status.html", include_bytes!("../assets/status.html")),
+    ("lock.html", include_bytes!("../assets/lock.html")),
+    (
+        "maintenance.html",
+        include_bytes!("../assets/maintenance.html"),
+    ),
+    ("style.css", include_bytes!("../assets/style.css")),
+    ("logo.png", LOGO),
+];
+
+/// Query parameters, which can be displayed by the bundled pages
+const PAGE_VARS: &[&str] = &["message", "url", "error"];
+
+static LOGO_URI: Lazy<String> = Lazy::new(|| {
+    format!(
+        "data:image/png;base64,{}",
+        base64::engine::general_purpose::STANDARD.encode(LOGO)
+    )
+});
+
+static PAGES_DIR: RwLock<Option<PathBuf>> = RwLock::new(None);
+
+pub fn set_pages_dir(dir: Option<&str>) {
+    *PAGES_DIR.write().unwrap() = dir.map(|d| PathBuf::from(shellexpand::tilde(d).as_ref()));
+}
+
+/// Returns a problem if the local pages directory is not valid
+pub fn check_pages_dir(dir: &str) -> Option<String> {
+    let path = shellexpand::tilde(dir);
+    match std::fs::metadata(path.as_ref()) {
+        Ok(m) if m.is_dir() => None,
+        Ok(_) => Some(format!("{} is not a directory", dir)),
+        Err(e) => Some(format!("{}: {}", dir, e)),
+    }
+}
+
+/// Internal pages are always allowed by the navigation policy
+#[cfg(target_os = "linux")]
+pub fn is_internal(url: &str) -> bool {
+    Url::parse(url).is_ok_and(|u| u.scheme() == SCHEME)
+}
+
+/// Internal pages are always allowed by the navigation policy, the host must be exactly
+/// evapanel.app or evapanel.local
+#[cfg(target_os = "windows")]
+pub fn is_internal(url: &str) -> bool {
+    Url::parse(url).is_ok_and(|u| {
+        matches!(u.scheme(), "http" | "https")
+            && u.port().is_none()
+            && u.host_str()
+                .and_then(|h| h.strip_prefix(SCHEME))
+                .and_then(|h| h.strip_prefix('.'))
+                .is_some_and(|h| h == APP_HOST || h == LOCAL_HOST)
+    })
+}
+
+fn html_escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&#39;")
+}
+
+/// Replaces "{{name}}" placeholders with HTML-escaped values, "logo" (a data URI),
+/// "home_url" and "version" are always set, unknown placeholders are removed
+pub fn render(template: &str, vars: &[(&str, &str)]) -> String {
+    let home_url = crate::home_url();
+    let mut result = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(start) = rest.find("{{") {
+        result.push_str(&rest[..start]);
+        let after = &rest[start + 2..];
+        let Some(end) = after.find("}}") else {
+            rest = &rest[start..];
+            break;
+        };
+        match &after[..end] {
+            "logo" => result.push_str(&LOGO_URI),
+            "home_url" => result.push_str(&html_escape(&home_url)),
+            "version" => result.push_str(crate::VERSION),
+            name => {
+                if let Some((_, value)) = vars.iter().find(|(n, _)| *n == name) {
+                    result.push_str(&html_escape(value));
+                }
+            }
+        }
+        rest = &after[end + 2..];
+    }
+    result.push_str(rest);
+    result
+}
+
+fn mime_type(path: &str) -> &'static str {
+    let ext = Path::new(path)
+        .extension()
+        .map(|ext| ext.to_string_lossy().to_ascii_lowercase())
+        .unwrap_or_default();
+    match ext.as_str() {
+        "html" | "htm" => "text/html; charset=utf-8",
+        "css" => "text/css",
+        "js" | "mjs" => "text/javascript",
+        "json" => "application/json",
+        "wasm" => "application/wasm",
+        "png" => "image/png",
+        "jpg" | "jpeg" => "image/jpeg",
+        "gif" => "image/gif",
+        "svg" => "image/svg+xml",
+        "ico" => "image/x-icon",
+        "webp" => "image/webp",
+        "woff" => "font/woff",
+        "woff2" => "font/woff2",
+        "ttf" => "font/ttf",
+        "txt" => "text/plain; charset=utf-8",
+        _ => "application/octet-stream",
+    }
+}
+
+fn response(
+    status: StatusCode,
+    path: &str,
+    body: Cow<'static, [u8]>,
+) -> Response<Cow<'static, [u8]>> {
+    Response::builder()
+        .status(status)
+        .header(CONTENT_TYPE, mime_type(path))
+        .body(body)
+        .unwrap_or_else(|_| Response::new(Cow::Borrowed(&[])))
+}
+
+/// The bundled error page, retries with backoff and navigates to the home URL once the server
+/// answers
+pub fn error_page(url: &str, error: &str) -> String {
+    render(
+        &String::from_utf8_lossy(resource("error.html").unwrap_or_default()),
+        &[("url", url), ("error", error)],
+    )
+}
+
+fn not_found(url: &str) -> Response<Cow<'static, [u8]>> {
+    response(
+        StatusCode::NOT_FOUND,
+        "error.html",
+        Cow::Owned(error_page(url, "not found").into_bytes()),
+    )
+}
+
+fn resource(name: &str) -> Option<&'static [u8]> {
+    RESOURCES
+        .iter()
+        .find(|(n, _)| *n == name)
+        .map(|(_, data)| *data)
+}
+
+fn serve_app(path: &str, url: &Url) -> Option<Response<Cow<'static, [u8]>>> {
+    let data = resource(path)?;
+    let body = if path.ends_with(".html") {
+        let query: Vec<(String, String)> = url
+            .query_pairs()
+            .filter(|(k, _)| PAGE_VARS.contains(&k.as_ref()))
+            .map(|(k, v)| (k.into_owned(), v.into_owned()))
+            .collect();
+        let vars: Vec<(&str, &str)> = query
+            .iter()
+            .map(|(k, v)| (k.as_str(), v.as_str()))
+            .collect();
+        Cow::Owned(render(&String::from_utf8_lossy(data), &vars).into_bytes())
+    } else {
+        Cow::Borrowed(data)
+    };
+    Some(response(StatusCode::OK, path, body))
+}
+
+/// Files outside of the directory (including ones, linked with symlinks) are never served
+fn local_file(dir: &Path, path: &str) -> Option<PathBuf> {
+    let decoded = percent_encoding::percent_decode_str(path)
+        .decode_utf8()
+        .ok()?;
+    let relative = Path::new(decoded.as_ref());
+    if relative
+        .components()
+        .any(|c| !matches!(c, Component::Normal(_)))
+    {
+        return None;
+    }
+    let dir = dir.canonicalize().ok()?;
+    let mut file = dir.join(relative);
+    if file.is_dir() {
+        file.push(INDEX);
+    }
+    let file = file.canonicalize().ok()?;
+    (file.starts_with(&dir) && file.is_file()).then_some(file)
+}
+
+fn serve_local(path: &str) -> Option<Response<Cow<'static, [u8]>>> {
+    let dir = PAGES_DIR.read().unwrap().clone()?;
+    let file = local_file(&dir, path)?;
+    match std::fs::read(&file) {
+        Ok(data) => Some(response(
+            StatusCode::OK,
+            &file.to_string_lossy(),
+            Cow::Owned(data),
+        )),
+        Err(e) => {
+            warn!("unable to read {}: {}", file.display(), e);
+            None
+        }
+    }
+}
+
+/// The custom protocol handler
+pub fn handle(request: &Request<Vec<u8>>) -> Response<Cow<'static, [u8]>> {
+    let uri = request.uri().to_string();
+    debug!("internal request: {}", uri);
+    let Ok(url) = Url::parse(&uri) else {
+        return not_found(&uri);
+    };
+    let host = url.host_str().unwrap_or_default();
+    let host = host
+        .strip_prefix(SCHEME)
+        .and_then(|h| h.strip_prefix('.'))
+        .unwrap_or(host);
+    let path = url.path().trim_start_matches('/');
+    let response = match host {
+        APP_HOST => serve_app(if path.is_empty() { "splash.html" } else { path }, &url),
+        LOCAL_HOST => serve_local(path),
+        _ => None,
+    };
+    response.unwrap_or_else(|| not_found(&uri))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Creates a pages dir with an outside file
+    fn pages_dir(name: &str) -> (PathBuf, PathBuf) {
+        let root = std::env::temp_dir().join(format!("evapanel-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&root);
+        let dir = root.join("pages");
+        std::fs::create_dir_all(dir.join("sub")).unwrap();
+        std::fs::write(dir.join(INDEX), "index").unwrap();
+        std::fs::write(dir.join("sub").join(INDEX), "sub").unwrap();
+        std::fs::write(dir.join("page 1.html"), "page").unwrap();
+        std::fs::write(root.join("secret"), "secret").unwrap();
+        (root, dir.canonicalize().unwrap())
+    }
+
+    #[test]
+    fn test_local_file() {
+        let (root, dir) = pages_dir("local");
+        assert_eq!(local_file(&dir, ""), Some(dir.join(INDEX)));
+        assert_eq!(local_file(&dir, "sub"), Some(dir.join("sub").join(INDEX)));
+        assert_eq!(
+            local_file(&dir, "page%201.html"),
+            Some(dir.join("page 1.html"))
+        );
+        assert!(local_file(&dir, "missing.html").is_none());
+        std::fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn test_local_file_traversal() {
+        let (root, dir) = pages_dir("traversal");
+        for path in [
+            "../secret",
+            "sub/../../secret",
+            "%2e%2e/secret",
+            "%2E%2E%2Fsecret",
+            "sub/%2e%2e/%2e%2e/secret",
+            "./index.html",
+            "..",
+        ] {
+            assert!(local_file(&dir, path).is_none(), "{}", path);
+        }
+        // absolute paths
+        let secret = root.join("secret");
+        let secret = secret.to_string_lossy();
+        assert!(local_file(&dir, &secret).is_none());
+        assert!(local_file(&dir, &secret.replace('/', "%2F")).is_none());
+        std::fs::remove_dir_all(root).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_local_file_symlinks() {
+        let (root, dir) = pages_dir("symlinks");
+        std::os::unix::fs::symlink(root.join("secret"), dir.join("file.html")).unwrap();
+        std::os::unix::fs::symlink(&root, dir.join("root")).unwrap();
+        std::os::unix::fs::symlink(dir.join("sub"), dir.join("link")).unwrap();
+        assert!(local_file(&dir, "file.html").is_none());
+        assert!(local_file(&dir, "root/secret").is_none());
+        assert!(local_file(&dir, "root").is_none());
+        // links inside the directory are allowed
+        assert_eq!(local_file(&dir, "link"), Some(dir.join("sub").join(INDEX)));
+        std::fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn test_render() {
+        assert_eq!(
+            render(
+                "<p>{{message}}</p><a href=\"{{url}}\">{{unknown}}</a>",
+                &[
+                    ("message", "<script>alert('x')</script> & more"),
+                    ("url", "http://eva/?a=1&b=\"2\""),
+                ],
+            ),
+            "<p>&lt;script&gt;alert(&#39;x&#39;)&lt;/script&gt; &amp; more</p>\
+            <a href=\"http://eva/?a=1&amp;b=&quot;2&quot;\"></a>"
+        );
+        assert_eq!(render("v{{version}}", &[]), format!("v{}", crate::VERSION));
+        assert_eq!(render("{{message}", &[("message", "x")]), "{{message}");
+        // values are not rendered recursively
+        assert_eq!(render("{{message}}", &[("message", "{{url}}")]), "{{url}}");
+    }
+}
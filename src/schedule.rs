@@ -0,0 +1,323 @@
+use chrono::{Datelike, Local, NaiveDateTime, NaiveTime, TimeDelta, Weekday};
+use eva_common::err_logger;
+use log::info;
+use serde::Deserialize;
+use std::sync::RwLock;
+use std::time::Duration;
+
+err_logger!();
+
+const CHECK_INTERVAL: Duration = Duration::from_secs(10);
+const TIME_FORMAT: &str = "%H:%M";
+
+static SCHEDULE: RwLock<Vec<ScheduleRule>> = RwLock::new(Vec::new());
+
+#[derive(Deserialize, Copy, Clone, Eq, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Day {
+    Mon,
+    Tue,
+    Wed,
+    Thu,
+    Fri,
+    Sat,
+    Sun,
+}
+
+impl From<Day> for Weekday {
+    fn from(day: Day) -> Self {
+        match day {
+            Day::Mon => Weekday::Mon,
+            Day::Tue => Weekday::Tue,
+            Day::Wed => Weekday::Wed,
+            Day::Thu => Weekday::Thu,
+            Day::Fri => Weekday::Fri,
+            Day::Sat => Weekday::Sat,
+            Day::Sun => Weekday::Sun,
+        }
+    }
+}
+
+/// A scheduled display transition
+#[derive(Deserialize, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct ScheduleRule {
+    /// week days, the rule is active every day if empty
+    #[serde(default)]
+    days: Vec<Day>,
+    /// local time, HH:MM
+    at: String,
+    #[serde(default)]
+    on: Option<bool>,
+    #[serde(default)]
+    brightness: Option<f32>,
+}
+
+impl ScheduleRule {
+    /// Returns the latest rule occurrence, which is not later than now
+    fn last_occurrence(&self, now: NaiveDateTime) -> Option<NaiveDateTime> {
+        let time = NaiveTime::parse_from_str(&self.at, TIME_FORMAT).ok()?;
+        (0..=7).find_map(|days_ago| {
+            let date = now.date() - TimeDelta::days(days_ago);
+            if self.days.is_empty()
+                || self
+                    .days
+                    .iter()
+                    .any(|d| Weekday::from(*d) == date.weekday())
+            {
+                Some(date.and_time(time)).filter(|t| *t <= now)
+            } else {
+                None
+            }
+        })
+    }
+}
+
+/// Returns a list of (key, problem)
+pub fn validate(rules: &[ScheduleRule]) -> Vec<(&'static str, String)> {
+    let mut problems = Vec::new();
+    for rule in rules {
+        if NaiveTime::parse_from_str(&rule.at, TIME_FORMAT).is_err() {
+            problems.push((
+                "display_schedule",
+                format!("invalid time {}, HH:MM expected", rule.at),
+            ));
+        }
+        if rule.on.is_none() && rule.brightness.is_none() {
+            problems.push((
+                "display_schedule",
+                format!("rule at {} has neither on nor brightness set", rule.at),
+            ));
+        }
+        if let Some(brightness) = rule.brightness {
+            if !(0.0..=100.0).contains(&brightness) {
+                problems.push((
+                    "display_schedule",
+                    format!("brightness {} is out of range 0..100%", brightness),
+                ));
+            }
+        }
+    }
+    problems
+}
+
+pub fn set(rules: Vec<ScheduleRule>) {
+    *SCHEDULE.write().unwrap() = rules;
+}
+
+pub fn is_active() -> bool {
+    !SCHEDULE.read().unwrap().is_empty()
+}
+
+/// The display state the schedule requires at the moment: the latest power and brightness
+/// transitions
+fn current(rules: &[ScheduleRule], now: NaiveDateTime) -> (Option<bool>, Option<f32>) {
+    let mut power: Option<(NaiveDateTime, bool)> = None;
+    let mut brightness: Option<(NaiveDateTime, f32)> = None;
+    for rule in rules {
+        let Some(t) = rule.last_occurrence(now) else {
+            continue;
+        };
+        if let Some(on) = rule.on {
+            if power.is_none_or(|(prev, _)| t >= prev) {
+                power = Some((t, on));
+            }
+        }
+        if let Some(br) = rule.brightness {
+            if brightness.is_none_or(|(prev, _)| t >= prev) {
+                brightness = Some((t, br));
+            }
+        }
+    }
+    (power.map(|v| v.1), brightness.map(|v| v.1))
+}
+
+/// Rules, which have fired after the previous check, ordered by their occurrence
+fn fired(
+    rules: &[ScheduleRule],
+    prev: NaiveDateTime,
+    now: NaiveDateTime,
+) -> Vec<(Option<bool>, Option<f32>)> {
+    let mut fired: Vec<(NaiveDateTime, Option<bool>, Option<f32>)> = rules
+        .iter()
+        .filter_map(|rule| {
+            rule.last_occurrence(now)
+                .filter(|t| *t > prev)
+                .map(|t| (t, rule.on, rule.brightness))
+        })
+        .collect();
+    fired.sort_by_key(|v| v.0);
+    fired.into_iter().map(|(_, on, br)| (on, br)).collect()
+}
+
+async fn apply(on: Option<bool>, brightness: Option<f32>) {
+    if on.is_none() && brightness.is_none() {
+        return;
+    }
+    info!(
+        "display schedule transition, on: {}, brightness: {}",
+        on.map_or_else(|| "-".to_owned(), |v| v.to_string()),
+        brightness.map_or_else(|| "-".to_owned(), |v| format!("{}%", v))
+    );
+    crate::display::apply(on, brightness).await.log_ef();
+}
+
+/// Applies scheduled display transitions. The current scheduled state is applied on start and
+/// when the schedule is changed, a manual change (e.g. via RPC) is kept until the next
+/// transition
+pub async fn run() {
+    let mut running: Option<Vec<ScheduleRule>> = None;
+    let mut prev = Local::now().naive_local();
+    loop {
+        let now = Local::now().naive_local();
+        let rules = SCHEDULE.read().unwrap().clone();
+        if running.as_ref() == Some(&rules) {
+            for (on, brightness) in fired(&rules, prev, now) {
+                apply(on, brightness).await;
+            }
+        } else {
+            if !rules.is_empty() {
+                let (on, brightness) = current(&rules, now);
+                apply(on, brightness).await;
+            }
+            running = Some(rules);
+        }
+        prev = now;
+        tokio::time::sleep(CHECK_INTERVAL).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::NaiveDate;
+
+    fn rule(days: &[Day], at: &str, on: Option<bool>, brightness: Option<f32>) -> ScheduleRule {
+        ScheduleRule {
+            days: days.to_vec(),
+            at: at.to_owned(),
+            on,
+            brightness,
+        }
+    }
+
+    /// 2024-01-01 is Monday
+    fn dt(day: u32, time: &str) -> NaiveDateTime {
+        NaiveDate::from_ymd_opt(2024, 1, day)
+            .unwrap()
+            .and_time(NaiveTime::parse_from_str(time, TIME_FORMAT).unwrap())
+    }
+
+    #[test]
+    fn last_occurrence() {
+        let daily = rule(&[], "08:00", Some(true), None);
+        assert_eq!(daily.last_occurrence(dt(3, "08:00")), Some(dt(3, "08:00")));
+        assert_eq!(daily.last_occurrence(dt(3, "07:59")), Some(dt(2, "08:00")));
+        assert!(rule(&[], "25:00", Some(true), None)
+            .last_occurrence(dt(3, "08:00"))
+            .is_none());
+    }
+
+    #[test]
+    fn weekdays() {
+        let workdays = rule(
+            &[Day::Mon, Day::Tue, Day::Wed, Day::Thu, Day::Fri],
+            "08:00",
+            Some(true),
+            None,
+        );
+        // Saturday and Sunday are skipped
+        assert_eq!(
+            workdays.last_occurrence(dt(7, "12:00")),
+            Some(dt(5, "08:00"))
+        );
+        // Monday before the time
+        assert_eq!(
+            workdays.last_occurrence(dt(8, "07:00")),
+            Some(dt(5, "08:00"))
+        );
+        assert_eq!(
+            workdays.last_occurrence(dt(8, "08:00")),
+            Some(dt(8, "08:00"))
+        );
+    }
+
+    #[test]
+    fn week_wrap() {
+        let monday = rule(&[Day::Mon], "08:00", Some(true), None);
+        // the previous week, a day before the next occurrence
+        assert_eq!(monday.last_occurrence(dt(7, "23:59")), Some(dt(1, "08:00")));
+        // the same weekday, before the time: exactly a week ago
+        assert_eq!(monday.last_occurrence(dt(8, "07:59")), Some(dt(1, "08:00")));
+        let sunday = rule(&[Day::Sun], "22:00", Some(false), None);
+        assert_eq!(
+            sunday.last_occurrence(dt(10, "12:00")),
+            Some(dt(7, "22:00"))
+        );
+        // a week ago, the previous year
+        assert_eq!(
+            sunday.last_occurrence(dt(7, "21:00")),
+            Some(dt(7, "22:00") - TimeDelta::days(7))
+        );
+    }
+
+    #[test]
+    fn current_state() {
+        let rules = [
+            rule(&[], "08:00", Some(true), Some(100.0)),
+            rule(&[], "20:00", None, Some(30.0)),
+            rule(
+                &[Day::Mon, Day::Tue, Day::Wed, Day::Thu, Day::Fri],
+                "22:00",
+                Some(false),
+                None,
+            ),
+        ];
+        assert_eq!(current(&rules, dt(3, "12:00")), (Some(true), Some(100.0)));
+        assert_eq!(current(&rules, dt(3, "21:00")), (Some(true), Some(30.0)));
+        assert_eq!(current(&rules, dt(3, "23:00")), (Some(false), Some(30.0)));
+        assert_eq!(current(&rules, dt(4, "07:00")), (Some(false), Some(30.0)));
+        // no off transition on Saturday
+        assert_eq!(current(&rules, dt(6, "23:00")), (Some(true), Some(30.0)));
+        assert_eq!(current(&[], dt(6, "23:00")), (None, None));
+    }
+
+    #[test]
+    fn same_time() {
+        let rules = [
+            rule(&[], "08:00", Some(true), Some(100.0)),
+            rule(&[], "08:00", Some(false), None),
+        ];
+        // the later rule wins
+        assert_eq!(current(&rules, dt(3, "09:00")), (Some(false), Some(100.0)));
+        // both rules are fired in the order of the schedule
+        assert_eq!(
+            fired(&rules, dt(3, "07:59"), dt(3, "08:00")),
+            vec![(Some(true), Some(100.0)), (Some(false), None)]
+        );
+    }
+
+    #[test]
+    fn fired_rules() {
+        let rules = [
+            rule(&[], "20:00", None, Some(30.0)),
+            rule(&[], "08:00", Some(true), None),
+            rule(&[Day::Sat], "09:00", Some(false), None),
+        ];
+        assert!(fired(&rules, dt(3, "08:00"), dt(3, "08:10")).is_empty());
+        assert_eq!(
+            fired(&rules, dt(3, "07:59"), dt(3, "08:00")),
+            vec![(Some(true), None)]
+        );
+        // ordered by the occurrence, the weekday filter is applied
+        assert_eq!(
+            fired(&rules, dt(5, "19:00"), dt(6, "10:00")),
+            vec![(None, Some(30.0)), (Some(true), None), (Some(false), None)]
+        );
+        // across the midnight
+        assert_eq!(
+            fired(&rules, dt(7, "23:59"), dt(8, "08:00")),
+            vec![(Some(true), None)]
+        );
+    }
+}